@@ -1,9 +1,41 @@
 # Adapted from https://github.com/replit/rippkgs/blob/main/lib/default.nix
-{lib, ...}: let
+{
+  lib,
+  # Also record pname and the meta attributes used by the package database
+  withMeta ? false,
+  ...
+}: let
+  metaFields = [
+    "description"
+    "longDescription"
+    "branch"
+    "homepage"
+    "downloadPage"
+    "changelog"
+    "license"
+    "maintainers"
+    "mainProgram"
+    "platforms"
+    "badPlatforms"
+    "broken"
+    "unfree"
+    "insecure"
+  ];
 in {
   genRegistry = platform: pkgs: let
-    inherit (builtins) deepSeq filter listToAttrs map parseDrvName seq tryEval;
-    inherit (lib) filterAttrs flatten foldl isDerivation mapAttrsToList optional optionals removePrefix traceVal;
+    inherit (builtins) concatMap deepSeq filter listToAttrs map parseDrvName seq tryEval;
+    inherit (lib) filterAttrs flatten foldl isDerivation mapAttrsToList optional optionals optionalAttrs removePrefix traceVal;
+
+    # Fields that fail to evaluate are left out rather than dropping the whole package
+    getMeta = meta:
+      listToAttrs (concatMap (field: let
+        value = tryEval (deepSeq (meta.${field} or null) (meta.${field} or null));
+      in
+        optional (value.success && value.value != null) {
+          name = field;
+          inherit (value) value;
+        })
+      metaFields);
 
     registerPackage = name: value: let
       safeValue = tryEval value;
@@ -21,9 +53,13 @@ in {
           };
 
           outputs-list = map getOutput (safeVal.outputs or []);
-          relevant-outputs = filter ({name, ...}: name == "out") outputs-list;
+          relevant-outputs = filter ({value, ...}: value != "<broken>") outputs-list;
         in
           listToAttrs relevant-outputs;
+      }
+      // optionalAttrs withMeta {
+        pname = safeVal.pname or (parseDrvName (safeVal.name or "")).name;
+        meta = getMeta (safeVal.meta or {});
       };
 
       platformForAvailability = {system = platform;};
@@ -53,7 +89,7 @@ in {
         }
         {
           reason = "broken outpath";
-          ok = safeRegistryValue.value.storePaths != { out = "<broken>"; } && safeRegistryValue.value.storePaths != {};
+          ok = safeRegistryValue.value.storePaths != {};
         }
       ];
     in
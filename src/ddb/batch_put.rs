@@ -4,14 +4,30 @@ use aws_sdk_dynamodb::{
     types::{AttributeValue, PutRequest, ReturnConsumedCapacity, WriteRequest},
     Client,
 };
-use log::{error, info, trace};
-use std::collections::HashMap;
+use log::{error, info, trace, warn};
+use rand::Rng;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+/// Attempts made for each batch before its unprocessed items are given up on
+const MAX_ATTEMPTS: u32 = 8;
+const BASE_DELAY: Duration = Duration::from_millis(50);
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Store paths that were still unprocessed after `MAX_ATTEMPTS`
+    pub unprocessed: Vec<String>,
+    /// Write capacity units consumed across all requests, including retries
+    pub consumed_capacity: f64,
+}
 
 pub async fn batch_store_put(
     client: &Client,
     store: &HashMap<String, Store>,
     table: &str,
-) -> Result<()> {
+    concurrency: usize,
+) -> Result<BatchReport> {
     let ops = store
         .iter()
         .map(|(k, v)| {
@@ -19,7 +35,9 @@ pub async fn batch_store_put(
                 .set_put_request(Some({
                     let mut putreq = PutRequest::builder()
                         .item("store", AttributeValue::S(k.to_string()))
-                        .item("attribute", AttributeValue::L(v.attribute.iter().map(|x| AttributeValue::S(x.to_string())).collect::<Vec<AttributeValue>>()));
+                        .item("attribute", AttributeValue::L(v.attribute.iter().map(|x| AttributeValue::S(x.to_string())).collect::<Vec<AttributeValue>>()))
+                        .item("system", AttributeValue::S(v.system.clone()))
+                        .item("output", AttributeValue::S(v.output.clone()));
                     if let Some(version) = &v.version {
                         putreq = putreq.item("version", AttributeValue::S(version.clone()));
                     }
@@ -29,11 +47,58 @@ pub async fn batch_store_put(
         })
         .collect::<Vec<WriteRequest>>();
 
+    let mut report = BatchReport::default();
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
     // Iterate over 25 items at a time
     let batches = ops.chunks(25);
     info!("Batches: {:?}", batches.len());
     for batch in batches {
-        let unprocessed = Some(HashMap::from([(table.to_string(), batch.to_vec())]));
+        let permit = semaphore.clone().acquire_owned().await?;
+        let client = client.clone();
+        let table = table.to_string();
+        let batch = batch.to_vec();
+        tasks.spawn(async move {
+            let result = put_batch(&client, &table, batch).await;
+            drop(permit);
+            result
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        let (unprocessed, consumed_capacity) = result??;
+        report.unprocessed.extend(unprocessed);
+        report.consumed_capacity += consumed_capacity;
+    }
+
+    info!("Consumed capacity: {} units", report.consumed_capacity);
+
+    if !report.unprocessed.is_empty() {
+        error!(
+            "{} items were never written: {:?}",
+            report.unprocessed.len(),
+            report.unprocessed
+        );
+    }
+
+    Ok(report)
+}
+
+/// Write a single batch, retrying unprocessed items
+///
+/// Returns the store paths that were never written and the capacity units consumed.
+async fn put_batch(
+    client: &Client,
+    table: &str,
+    batch: Vec<WriteRequest>,
+) -> Result<(Vec<String>, f64)> {
+    let mut pending = batch;
+    let mut consumed_capacity = 0.0;
+    let mut attempt = 0;
+    while !pending.is_empty() {
+        attempt += 1;
+        let unprocessed = Some(HashMap::from([(table.to_string(), pending.clone())]));
         let out = client
             .batch_write_item()
             .set_request_items(unprocessed)
@@ -41,14 +106,66 @@ pub async fn batch_store_put(
             .send()
             .await;
 
-        if out.is_err() {
-            error!("{:?}", out);
-            error!("{:?}", batch);
-            anyhow::bail!("Failed to batch write items")
-        }
+        let out = match out {
+            Ok(out) => out,
+            Err(e) => {
+                error!("{:?}", e);
+                error!("{:?}", pending);
+                anyhow::bail!("Failed to batch write items")
+            }
+        };
 
         trace!("Results: {:?}", out);
+
+        consumed_capacity += out
+            .consumed_capacity()
+            .iter()
+            .filter_map(|x| x.capacity_units())
+            .sum::<f64>();
+
+        pending = out
+            .unprocessed_items()
+            .and_then(|x| x.get(table))
+            .cloned()
+            .unwrap_or_default();
+
+        if pending.is_empty() {
+            break;
+        } else if attempt >= MAX_ATTEMPTS {
+            return Ok((
+                pending.iter().filter_map(store_key).collect(),
+                consumed_capacity,
+            ));
+        }
+
+        let delay = backoff(attempt);
+        warn!(
+            "{} items unprocessed, retrying in {:?} (attempt {}/{})",
+            pending.len(),
+            delay,
+            attempt + 1,
+            MAX_ATTEMPTS
+        );
+        tokio::time::sleep(delay).await;
     }
 
-    Ok(())
+    Ok((vec![], consumed_capacity))
+}
+
+/// Exponential backoff with full jitter
+fn backoff(attempt: u32) -> Duration {
+    let cap = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_DELAY);
+    rand::thread_rng().gen_range(Duration::ZERO..=cap)
+}
+
+fn store_key(request: &WriteRequest) -> Option<String> {
+    request
+        .put_request()?
+        .item()
+        .get("store")?
+        .as_s()
+        .ok()
+        .cloned()
 }
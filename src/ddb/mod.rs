@@ -2,12 +2,16 @@ use serde::{Deserialize, Serialize};
 
 pub mod nix;
 pub mod batch_put;
+pub mod sink;
 
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Store {
     pub attribute: Vec<String>,
     pub version: Option<String>,
+    pub system: String,
+    /// Derivation output the store path belongs to, e.g. `out`, `dev` or `man`
+    pub output: String,
 }
 
 pub const REGISTRY: &str = "./registry.nix";
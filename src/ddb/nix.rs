@@ -1,42 +1,78 @@
-use crate::ddb::REGISTRY;
+use crate::{ddb::REGISTRY, fixtures::Fixtures, nixpkgs::NixpkgsSource, registry, Pkg};
 
 use super::Store;
-use log::{debug, error, info, warn};
-use serde::Deserialize;
+use log::{error, info, warn};
 use std::collections::HashMap;
 use tokio::process::Command;
 use anyhow::Result;
 
-#[derive(Deserialize, Debug, Clone)]
-struct Package {
-    #[serde(rename = "storePaths")]
-    outputs: HashMap<String, String>,
-    version: Option<String>,
+/// Fetch nixpkgs at `rev`, returning the path to evaluate it from, or nothing with fixtures
+///
+/// Unlike a failed evaluation, a failed fetch says nothing about the revision itself.
+pub async fn fetch_nixpkgs(
+    rev: &str,
+    nixpkgs: &NixpkgsSource,
+    fixtures: Option<&Fixtures>,
+) -> Result<String> {
+    match fixtures {
+        Some(_) => Ok(String::new()),
+        None => nixpkgs.resolve(rev).await,
+    }
 }
 
-pub async fn get_store(rev: &str) -> Result<HashMap<String, Store>> {
-    let nixpath = Command::new("nix-instantiate")
-        .arg("--eval")
-        .arg("-E")
-        .arg(format!("with import <nixpkgs> {{}}; pkgs.path"))
-        .arg("-I")
-        .arg(format!(
-            "nixpkgs=https://github.com/NixOS/nixpkgs/archive/{}.tar.gz",
-            rev
-        ))
-        .output()
-        .await
-        .expect("failed to execute process");
-    if !nixpath.status.success() {
-        error!(
-            "nix-instantiate failed: {}",
-            String::from_utf8_lossy(&nixpath.stderr)
-        );
-        std::process::exit(1);
+/// Store path index of nixpkgs at `rev`, fetched to `nixpath` by [`fetch_nixpkgs`]
+///
+/// Nothing is returned if any of `systems` fails to evaluate, as indexing the rest would leave
+/// that system's paths out for good. Missing fixtures are an error rather than a failed
+/// evaluation.
+pub async fn get_store(
+    rev: &str,
+    nixpath: &str,
+    systems: &[String],
+    fixtures: Option<&Fixtures>,
+) -> Result<Option<HashMap<String, Store>>> {
+    let mut store: HashMap<String, Store> = HashMap::new();
+    for system in systems {
+        let output = match fixtures {
+            Some(fixtures) => fixtures.registry(rev, system)?,
+            None => match eval_registry(rev, nixpath, system, false).await {
+                Ok(output) => output,
+                Err(_) => return Ok(None),
+            },
+        };
+
+        let output = registry::parse(&output)?;
+
+        info!("nix-instantiate: got {} packages for {}", output.len(), system);
+
+        registry::add_stores(&mut store, system, &output);
     }
-    let nixpath = String::from_utf8_lossy(&nixpath.stdout).trim().to_string();
-    debug!("nixpath: {}", nixpath);
 
+    info!("nix-instantiate: got {} store paths", store.len());
+
+    Ok(Some(store))
+}
+
+/// Packages and their metadata of nixpkgs at `rev` on `system`, without packages.json
+pub async fn get_packages(
+    rev: &str,
+    system: &str,
+    nixpkgs: &NixpkgsSource,
+    fixtures: Option<&Fixtures>,
+) -> Result<HashMap<String, Pkg>> {
+    let nixpath = fetch_nixpkgs(rev, nixpkgs, fixtures).await?;
+    let output = match fixtures {
+        Some(fixtures) => fixtures.registry(rev, system)?,
+        None => eval_registry(rev, &nixpath, system, true).await?,
+    };
+    let output = registry::parse(&output)?;
+
+    info!("nix-instantiate: got {} packages for {}", output.len(), system);
+
+    Ok(registry::packages(output))
+}
+
+async fn eval_registry(rev: &str, nixpath: &str, system: &str, with_meta: bool) -> Result<Vec<u8>> {
     let mut output = Command::new("nix-instantiate")
         .env("NIXPKGS_ALLOW_UNFREE", "1")
         .env("NIXPKGS_ALLOW_INSECURE", "1")
@@ -44,7 +80,7 @@ pub async fn get_store(rev: &str) -> Result<HashMap<String, Store>> {
         // .env("NIXPKGS_ALLOW_UNSUPPORTED_SYSTEM", "0")
         .arg("--eval")
         .arg("-E")
-        .arg(format!("with import {nixpath} {{ config = import {nixpath}/pkgs/top-level/packages-config.nix; }}; (import {REGISTRY} {{ inherit lib; }}).genRegistry \"x86_64-linux\" pkgs"))
+        .arg(format!("with import {nixpath} {{ system = \"{system}\"; config = import {nixpath}/pkgs/top-level/packages-config.nix; }}; (import {REGISTRY} {{ inherit lib; withMeta = {with_meta}; }}).genRegistry \"{system}\" pkgs"))
         .arg("-I")
         .arg(format!("nixpkgs={}", nixpath))
         .arg("--json")
@@ -62,7 +98,7 @@ pub async fn get_store(rev: &str) -> Result<HashMap<String, Store>> {
         // .env("NIXPKGS_ALLOW_UNSUPPORTED_SYSTEM", "0")
         .arg("--eval")
         .arg("-E")
-        .arg(format!("with import {nixpath} {{ config = {{ allowAliases = false; }}; }}; (import {REGISTRY} {{ inherit lib; }}).genRegistry \"x86_64-linux\" pkgs"))
+        .arg(format!("with import {nixpath} {{ system = \"{system}\"; config = {{ allowAliases = false; }}; }}; (import {REGISTRY} {{ inherit lib; withMeta = {with_meta}; }}).genRegistry \"{system}\" pkgs"))
         .arg("-I")
         .arg(format!("nixpkgs={}", nixpath))
         .arg("--json")
@@ -74,35 +110,11 @@ pub async fn get_store(rev: &str) -> Result<HashMap<String, Store>> {
 
     if !output.status.success() {
         error!(
-            "failed to eval revision: {}",
-            rev
+            "failed to eval revision: {} ({})",
+            rev, system
         );
-        return Err(anyhow::anyhow!("failed to eval revision: {}", rev));
+        return Err(anyhow::anyhow!("failed to eval revision: {} ({})", rev, system));
     }
 
-    let output: HashMap<String, Package> =
-        serde_json::from_slice(&output.stdout).expect("failed to parse nix-instantiate output");
-
-    info!("nix-instantiate: got {} packages", output.len());
-
-    let mut store: HashMap<String, Store> = HashMap::new();
-    for (attr, pkg) in &output {
-        if let Some(outpath) = pkg.outputs.get("out") {
-            if let Some(store_val) = store.get_mut(outpath) {
-                store_val.attribute.push(attr.to_string());
-            } else {
-                store.insert(
-                    outpath.to_string(),
-                    Store {
-                        attribute: vec![attr.to_string()],
-                        version: pkg.version.clone(),
-                    },
-                );
-            }
-        }
-    }
-
-    info!("nix-instantiate: got {} store paths", store.len());
-
-    return Ok(store);
+    Ok(output.stdout)
 }
@@ -0,0 +1,173 @@
+use super::{batch_put::batch_store_put, Store};
+use anyhow::{Context, Result};
+use aws_sdk_dynamodb::Client;
+use log::info;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Outcome of writing the store path index to a sink
+#[derive(Debug, Default)]
+pub struct PutReport {
+    /// Store paths that were never written
+    pub unprocessed: Vec<String>,
+}
+
+/// Somewhere the store path index can be written to
+pub trait StoreSink {
+    /// Write every entry of `store`, keyed by store path
+    fn put(&self, store: &HashMap<String, Store>)
+        -> impl Future<Output = Result<PutReport>> + Send;
+}
+
+/// DynamoDB table keyed by `store`
+#[derive(Debug, Clone)]
+pub struct DynamoDbSink {
+    client: Client,
+    table: String,
+    concurrency: usize,
+}
+
+impl DynamoDbSink {
+    pub fn new(client: Client, table: &str, concurrency: usize) -> Self {
+        DynamoDbSink {
+            client,
+            table: table.to_string(),
+            concurrency,
+        }
+    }
+}
+
+impl StoreSink for DynamoDbSink {
+    async fn put(&self, store: &HashMap<String, Store>) -> Result<PutReport> {
+        // Consumed capacity is specific to DynamoDB, so it is only logged
+        let report = batch_store_put(&self.client, store, &self.table, self.concurrency).await?;
+        Ok(PutReport {
+            unprocessed: report.unprocessed,
+        })
+    }
+}
+
+/// Local SQLite database with a single `store` table
+#[derive(Debug, Clone)]
+pub struct SqliteSink {
+    path: PathBuf,
+}
+
+impl SqliteSink {
+    pub fn new(path: &str) -> Self {
+        SqliteSink {
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn write(path: PathBuf, store: HashMap<String, Store>) -> Result<()> {
+        let mut conn = Connection::open(&path)
+            .with_context(|| format!("Failed to open database {}", path.display()))?;
+        // Channels processed concurrently share the database
+        conn.busy_timeout(Duration::from_secs(60))?;
+
+        conn.execute(
+            r#"CREATE TABLE IF NOT EXISTS "store" (
+                "store" TEXT NOT NULL UNIQUE,
+                "attribute" JSON NOT NULL,
+                "version" TEXT,
+                "system" TEXT NOT NULL,
+                "output" TEXT NOT NULL,
+                PRIMARY KEY("store")
+            )"#,
+            [],
+        )
+        .context("Failed to create table")?;
+
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                r#"INSERT OR REPLACE INTO "store" ("store", "attribute", "version", "system", "output") VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            )?;
+            for (path, v) in &store {
+                stmt.execute(params![
+                    path,
+                    serde_json::to_string(&v.attribute)?,
+                    v.version,
+                    v.system,
+                    v.output,
+                ])
+                .with_context(|| format!("Failed to insert {}", path))?;
+            }
+        }
+        tx.commit().context("Failed to commit database")?;
+
+        Ok(())
+    }
+}
+
+impl StoreSink for SqliteSink {
+    async fn put(&self, store: &HashMap<String, Store>) -> Result<PutReport> {
+        let path = self.path.clone();
+        let store = store.clone();
+        tokio::task::spawn_blocking(move || SqliteSink::write(path, store)).await??;
+        info!("Wrote store paths to {}", self.path.display());
+        Ok(PutReport::default())
+    }
+}
+
+/// Newline-delimited JSON, one object per store path, appended to a file
+#[derive(Debug, Clone)]
+pub struct NdjsonSink {
+    path: PathBuf,
+    // Keeps concurrent writers from interleaving lines
+    lock: Arc<Mutex<()>>,
+}
+
+impl NdjsonSink {
+    pub fn new(path: &str) -> Self {
+        NdjsonSink {
+            path: PathBuf::from(path),
+            lock: Arc::default(),
+        }
+    }
+
+    fn write(path: PathBuf, lock: Arc<Mutex<()>>, out: Vec<u8>) -> Result<()> {
+        let _guard = lock.lock().expect("Poisoned ndjson lock");
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(&out)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct NdjsonEntry<'a> {
+    store: &'a str,
+    #[serde(flatten)]
+    value: &'a Store,
+}
+
+impl StoreSink for NdjsonSink {
+    async fn put(&self, store: &HashMap<String, Store>) -> Result<PutReport> {
+        let mut out = vec![];
+        for (path, value) in store {
+            serde_json::to_writer(&mut out, &NdjsonEntry { store: path, value })?;
+            out.push(b'\n');
+        }
+
+        let path = self.path.clone();
+        let lock = self.lock.clone();
+        tokio::task::spawn_blocking(move || NdjsonSink::write(path, lock, out)).await??;
+
+        info!("Wrote store paths to {}", self.path.display());
+        Ok(PutReport::default())
+    }
+}
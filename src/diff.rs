@@ -0,0 +1,344 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{
+    fixtures::Fixtures,
+    meta::{License, Maintainer},
+    s3::nix::getmeta,
+    MetaData, Pkg,
+};
+
+/// Changes to a channel's package set between two releases
+#[derive(Debug, Serialize, Default, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub added: Vec<Package>,
+    pub removed: Vec<Package>,
+    pub version_changes: Vec<Change<String>>,
+    pub newly_broken: Vec<String>,
+    pub newly_insecure: Vec<String>,
+    pub newly_unfree: Vec<String>,
+    pub license_changes: Vec<Change<Vec<String>>>,
+    pub maintainer_changes: Vec<Change<Vec<String>>>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub attribute: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Change<T> {
+    pub attribute: String,
+    pub from: T,
+    pub to: T,
+}
+
+/// Fetch the package sets of two releases of `channel` and compare them
+pub async fn diff_releases(
+    channel: &str,
+    from: &str,
+    to: &str,
+    fixtures: Option<&Fixtures>,
+) -> Result<Diff> {
+    let old = getmeta(channel, from, fixtures).await?;
+    let new = getmeta(channel, to, fixtures).await?;
+    Ok(diff(&old, &new))
+}
+
+pub fn diff(old: &HashMap<String, Pkg>, new: &HashMap<String, Pkg>) -> Diff {
+    let mut out = Diff::default();
+
+    let mut attributes = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    attributes.sort();
+    attributes.dedup();
+
+    for attribute in attributes {
+        let (old, new) = match (old.get(attribute), new.get(attribute)) {
+            (None, Some(new)) => {
+                out.added.push(Package {
+                    attribute: attribute.to_string(),
+                    version: new.version.clone(),
+                });
+                continue;
+            }
+            (Some(old), None) => {
+                out.removed.push(Package {
+                    attribute: attribute.to_string(),
+                    version: old.version.clone(),
+                });
+                continue;
+            }
+            (Some(old), Some(new)) => (old, new),
+            (None, None) => continue,
+        };
+
+        if old.version != new.version {
+            out.version_changes.push(Change {
+                attribute: attribute.to_string(),
+                from: old.version.clone(),
+                to: new.version.clone(),
+            });
+        }
+
+        let newly = |flag: fn(&MetaData) -> Option<bool>| {
+            !flag(&old.meta).unwrap_or(false) && flag(&new.meta).unwrap_or(false)
+        };
+        if newly(|x| x.broken) {
+            out.newly_broken.push(attribute.to_string());
+        }
+        if newly(|x| x.insecure) {
+            out.newly_insecure.push(attribute.to_string());
+        }
+        if newly(|x| x.unfree) {
+            out.newly_unfree.push(attribute.to_string());
+        }
+
+        let (from, to) = (licenses(&old.meta), licenses(&new.meta));
+        if from != to {
+            out.license_changes.push(Change {
+                attribute: attribute.to_string(),
+                from,
+                to,
+            });
+        }
+
+        let (from, to) = (maintainers(&old.meta), maintainers(&new.meta));
+        if from != to {
+            out.maintainer_changes.push(Change {
+                attribute: attribute.to_string(),
+                from,
+                to,
+            });
+        }
+    }
+
+    out
+}
+
+fn licenses(meta: &MetaData) -> Vec<String> {
+    meta.license
+        .iter()
+        .flat_map(|x| x.iter())
+        .filter_map(license_name)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn license_name(license: &License) -> Option<String> {
+    let info = license.info()?;
+    info.spdx_id.or(info.short_name).or(info.full_name)
+}
+
+fn maintainers(meta: &MetaData) -> Vec<String> {
+    meta.maintainers
+        .iter()
+        .flat_map(|x| x.iter())
+        .filter_map(maintainer_name)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn maintainer_name(maintainer: &Maintainer) -> Option<String> {
+    let info = maintainer.info()?;
+    info.github.or(info.name).or(info.email)
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let section = |f: &mut fmt::Formatter<'_>, title: &str, lines: Vec<String>| {
+            if lines.is_empty() {
+                return Ok(());
+            }
+            writeln!(f, "{} ({}):", title, lines.len())?;
+            for line in lines {
+                writeln!(f, "  {}", line)?;
+            }
+            writeln!(f)
+        };
+
+        section(
+            f,
+            "Added",
+            self.added
+                .iter()
+                .map(|x| format!("{} {}", x.attribute, x.version))
+                .collect(),
+        )?;
+        section(
+            f,
+            "Removed",
+            self.removed
+                .iter()
+                .map(|x| format!("{} {}", x.attribute, x.version))
+                .collect(),
+        )?;
+        section(
+            f,
+            "Version changes",
+            self.version_changes
+                .iter()
+                .map(|x| format!("{}: {} -> {}", x.attribute, x.from, x.to))
+                .collect(),
+        )?;
+        section(f, "Newly broken", self.newly_broken.clone())?;
+        section(f, "Newly insecure", self.newly_insecure.clone())?;
+        section(f, "Newly unfree", self.newly_unfree.clone())?;
+        section(
+            f,
+            "License changes",
+            self.license_changes
+                .iter()
+                .map(|x| format!("{}: {:?} -> {:?}", x.attribute, x.from, x.to))
+                .collect(),
+        )?;
+        section(
+            f,
+            "Maintainer changes",
+            self.maintainer_changes
+                .iter()
+                .map(|x| format!("{}: {:?} -> {:?}", x.attribute, x.from, x.to))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn pkgs(entries: &[(&str, &str, Value)]) -> HashMap<String, Pkg> {
+        entries
+            .iter()
+            .map(|(attribute, version, meta)| {
+                let pkg = Pkg {
+                    attribute: attribute.to_string(),
+                    outputs: HashMap::new(),
+                    meta: serde_json::from_value(meta.clone()).unwrap(),
+                    pname: attribute.to_string(),
+                    version: version.to_string(),
+                };
+                (attribute.to_string(), pkg)
+            })
+            .collect()
+    }
+
+    fn package(attribute: &str, version: &str) -> Package {
+        Package {
+            attribute: attribute.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn added_removed_and_version_changes() {
+        let old = pkgs(&[
+            ("hello", "2.12", json!({})),
+            ("steam", "1.0", json!({})),
+            ("cowsay", "3.7", json!({})),
+        ]);
+        let new = pkgs(&[
+            ("hello", "2.13", json!({})),
+            ("cowsay", "3.7", json!({})),
+            ("zstd", "1.5", json!({})),
+        ]);
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.added, [package("zstd", "1.5")]);
+        assert_eq!(diff.removed, [package("steam", "1.0")]);
+        assert_eq!(
+            diff.version_changes,
+            [Change {
+                attribute: "hello".to_string(),
+                from: "2.12".to_string(),
+                to: "2.13".to_string(),
+            }]
+        );
+        assert_eq!(diff.newly_broken, Vec::<String>::new());
+    }
+
+    #[test]
+    fn newly_flags_only_when_set() {
+        let old = pkgs(&[
+            ("a", "1", json!({ "broken": false, "insecure": false })),
+            ("b", "1", json!({ "broken": true, "unfree": true })),
+            ("c", "1", json!({})),
+            ("d", "1", json!({})),
+            ("e", "1", json!({ "broken": true })),
+        ]);
+        let new = pkgs(&[
+            ("a", "1", json!({ "broken": true, "insecure": true })),
+            ("b", "1", json!({ "broken": true, "unfree": true })),
+            ("c", "1", json!({ "broken": false, "unfree": false })),
+            ("d", "1", json!({ "unfree": true })),
+            ("e", "1", json!({ "broken": false })),
+        ]);
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.newly_broken, ["a"]);
+        assert_eq!(diff.newly_insecure, ["a"]);
+        assert_eq!(diff.newly_unfree, ["d"]);
+    }
+
+    #[test]
+    fn license_and_maintainer_order_is_ignored() {
+        let old = pkgs(&[
+            (
+                "hello",
+                "1",
+                json!({
+                    "license": ["mit", { "spdxId": "Apache-2.0" }],
+                    "maintainers": [{ "github": "jane" }, { "github": "joe" }],
+                }),
+            ),
+            (
+                "steam",
+                "1",
+                json!({ "license": "mit", "maintainers": [{ "github": "jane" }] }),
+            ),
+        ]);
+        let new = pkgs(&[
+            (
+                "hello",
+                "1",
+                json!({
+                    "license": [{ "spdxId": "Apache-2.0" }, "mit"],
+                    "maintainers": [{ "github": "joe" }, { "github": "jane" }],
+                }),
+            ),
+            (
+                "steam",
+                "1",
+                json!({
+                    "license": [{ "shortName": "unfree" }, "mit"],
+                    "maintainers": [{ "github": "joe" }],
+                }),
+            ),
+        ]);
+
+        let diff = diff(&old, &new);
+        assert_eq!(
+            diff.license_changes,
+            [Change {
+                attribute: "steam".to_string(),
+                from: vec!["mit".to_string()],
+                to: vec!["mit".to_string(), "unfree".to_string()],
+            }]
+        );
+        assert_eq!(
+            diff.maintainer_changes,
+            [Change {
+                attribute: "steam".to_string(),
+                from: vec!["jane".to_string()],
+                to: vec!["joe".to_string()],
+            }]
+        );
+    }
+}
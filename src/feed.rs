@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use quick_xml::{events::BytesText, Writer};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    diff::{Change, Diff},
+    revisions::{source::Release, write_atomic},
+};
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// Version bumps listed per entry, top-level attributes first
+const NOTABLE_BUMPS: usize = 25;
+
+/// A channel release, as listed in the channel's feed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    pub release: String,
+    pub rev: String,
+    pub timestamp: String,
+    /// Release this one is compared against, if its packages could be fetched
+    pub previous: Option<String>,
+    pub added: usize,
+    pub removed: usize,
+    pub updated: usize,
+    pub newly_broken: usize,
+    pub notable: Vec<Change<String>>,
+}
+
+impl FeedEntry {
+    /// Entry for `release`, with `diff` against `previous` when there is one to compare against
+    pub fn new(previous: Option<&str>, release: &Release, rev: &str, diff: &Diff) -> Self {
+        let mut notable = diff.version_changes.clone();
+        // Package sets like python3Packages.* are noisy, so they only make the cut when
+        // there is room left after top-level packages
+        notable.sort_by_key(|x| x.attribute.contains('.'));
+        notable.truncate(NOTABLE_BUMPS);
+
+        FeedEntry {
+            release: release.name().to_string(),
+            rev: rev.to_string(),
+            timestamp: release.last_modified.clone(),
+            previous: previous.map(str::to_string),
+            added: diff.added.len(),
+            removed: diff.removed.len(),
+            updated: diff.version_changes.len(),
+            newly_broken: diff.newly_broken.len(),
+            notable,
+        }
+    }
+
+    fn title(&self) -> String {
+        if self.previous.is_none() {
+            return format!("{}: first release in the feed", self.release);
+        }
+        format!(
+            "{}: {} updated, {} added, {} removed",
+            self.release, self.updated, self.added, self.removed
+        )
+    }
+
+    fn content(&self) -> String {
+        let Some(previous) = &self.previous else {
+            return format!(
+                "{} (git revision {}), no earlier release to compare to\n",
+                self.release, self.rev
+            );
+        };
+        let mut out = format!(
+            "{} (git revision {}), compared to {}\n\n",
+            self.release, self.rev, previous
+        );
+        let _ = writeln!(out, "Updated: {}", self.updated);
+        let _ = writeln!(out, "Added: {}", self.added);
+        let _ = writeln!(out, "Removed: {}", self.removed);
+        let _ = writeln!(out, "Newly broken: {}", self.newly_broken);
+        if !self.notable.is_empty() {
+            out.push('\n');
+            for bump in &self.notable {
+                let _ = writeln!(out, "{}: {} -> {}", bump.attribute, bump.from, bump.to);
+            }
+            if self.updated > self.notable.len() {
+                let _ = writeln!(out, "and {} more", self.updated - self.notable.len());
+            }
+        }
+        out
+    }
+}
+
+/// Add `entry` to the feed of `channel` and rewrite `{output}/{channel}.atom`
+///
+/// Entries are kept in `{processed}/{channel}/feed.json`, newest first and at most
+/// `max_entries` of them.
+pub fn update_feed(
+    processed: &str,
+    output: &str,
+    channel: &str,
+    entry: FeedEntry,
+    max_entries: usize,
+) -> Result<()> {
+    let path = format!("{}/{}/feed.json", processed, channel);
+    let mut entries: Vec<FeedEntry> = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).context("Failed to parse feed entries")?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path)),
+    };
+
+    entries.retain(|x| x.release != entry.release);
+    entries.push(entry);
+    entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    entries.truncate(max_entries);
+
+    let feed = format!("{}/{}.atom", output, channel);
+    if let Some(parent) = Path::new(&feed).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&feed, &render(channel, &entries)?)?;
+    write_atomic(&path, &serde_json::to_vec_pretty(&entries)?)?;
+
+    Ok(())
+}
+
+/// Render `entries`, newest first, as an Atom feed
+pub fn render(channel: &str, entries: &[FeedEntry]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new_with_indent(vec![], b' ', 2);
+    let updated = entries
+        .first()
+        .map(|x| x.timestamp.as_str())
+        .unwrap_or_default();
+
+    writer
+        .create_element("feed")
+        .with_attribute(("xmlns", ATOM_NS))
+        .write_inner_content(|w| {
+            text(w, "id", &format!("urn:libsnow:{}", channel))?;
+            text(w, "title", &format!("{} releases", channel))?;
+            text(w, "updated", updated)?;
+            w.create_element("author")
+                .write_inner_content(|w| text(w, "name", "libsnow"))?;
+
+            for entry in entries {
+                w.create_element("entry").write_inner_content(|w| {
+                    text(
+                        w,
+                        "id",
+                        &format!("urn:libsnow:{}:{}", channel, entry.release),
+                    )?;
+                    text(w, "title", &entry.title())?;
+                    text(w, "updated", &entry.timestamp)?;
+                    w.create_element("content")
+                        .with_attribute(("type", "text"))
+                        .write_text_content(BytesText::new(&entry.content()))?;
+                    Ok::<_, quick_xml::Error>(())
+                })?;
+            }
+            Ok::<_, quick_xml::Error>(())
+        })?;
+
+    let mut out = br#"<?xml version="1.0" encoding="utf-8"?>"#.to_vec();
+    out.push(b'\n');
+    out.append(&mut writer.into_inner());
+    out.push(b'\n');
+    Ok(out)
+}
+
+fn text(w: &mut Writer<Vec<u8>>, name: &str, value: &str) -> quick_xml::Result<()> {
+    w.create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
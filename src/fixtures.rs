@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use std::{io::Read, path::PathBuf};
+
+use crate::flake::reference_name;
+
+/// Directory of pre-recorded inputs used in place of network and Nix access
+///
+/// ```text
+/// {root}/listing/{prefix}/page-{n}.xml                     ListBucket pages, starting at 0
+/// {root}/releases/{channel}/{release}/packages.json.br     as on releases.nixos.org
+/// {root}/releases/{channel}/{release}/git-revision
+/// {root}/registry/{system}/{rev}.json                      genRegistry of a full git revision
+/// {root}/flakes/{flake}/metadata.json                      nix flake metadata --json
+/// {root}/flakes/{flake}/{system}.json                      genRegistry with meta of the flake
+/// ```
+///
+/// Flake references are stored by [`reference_name`], e.g. `github_snowfallorg_flake`.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    root: PathBuf,
+}
+
+impl Fixtures {
+    pub fn new(root: &str) -> Self {
+        Fixtures {
+            root: PathBuf::from(root),
+        }
+    }
+
+    fn read(&self, path: PathBuf) -> Result<Vec<u8>> {
+        std::fs::read(&path).with_context(|| format!("Failed to read fixture {}", path.display()))
+    }
+
+    /// ListBucket XML for the `page`th request of a listing
+    pub fn listing_page(&self, prefix: &str, page: usize) -> Result<String> {
+        let path = self
+            .root
+            .join("listing")
+            .join(prefix)
+            .join(format!("page-{}.xml", page));
+        Ok(String::from_utf8(self.read(path)?)?)
+    }
+
+    /// Decompressed `packages.json.br` of a release
+    pub fn packages_json(&self, channel: &str, rev: &str) -> Result<Vec<u8>> {
+        let path = self
+            .root
+            .join("releases")
+            .join(channel)
+            .join(rev)
+            .join("packages.json.br");
+        let compressed = self.read(path)?;
+
+        let mut output = vec![];
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_end(&mut output)
+            .context("Failed to decompress packages.json.br")?;
+        Ok(output)
+    }
+
+    /// `git-revision` of a release
+    pub fn git_revision(&self, channel: &str, rev: &str) -> Result<String> {
+        let path = self
+            .root
+            .join("releases")
+            .join(channel)
+            .join(rev)
+            .join("git-revision");
+        Ok(String::from_utf8(self.read(path)?)?)
+    }
+
+    /// Pre-evaluated registry JSON for a nixpkgs revision on `system`
+    pub fn registry(&self, rev: &str, system: &str) -> Result<Vec<u8>> {
+        self.read(
+            self.root
+                .join("registry")
+                .join(system)
+                .join(format!("{}.json", rev)),
+        )
+    }
+
+    /// `nix flake metadata --json` of a flake reference
+    pub fn flake_metadata(&self, flake: &str) -> Result<Vec<u8>> {
+        self.read(self.flake_dir(flake).join("metadata.json"))
+    }
+
+    /// Pre-evaluated registry JSON, with meta, of a flake's packages on `system`
+    pub fn flake_registry(&self, flake: &str, system: &str) -> Result<Vec<u8>> {
+        self.read(self.flake_dir(flake).join(format!("{}.json", system)))
+    }
+
+    fn flake_dir(&self, flake: &str) -> PathBuf {
+        self.root.join("flakes").join(reference_name(flake))
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::process::Command;
+
+use crate::{
+    ddb::{Store, REGISTRY},
+    fixtures::Fixtures,
+    registry::{self, Registry},
+    Pkg,
+};
+
+/// A flake whose `packages.<system>`, and optionally `legacyPackages.<system>`, are indexed
+#[derive(Debug, Clone)]
+pub struct Flake {
+    pub reference: String,
+    pub legacy_packages: bool,
+}
+
+/// What a flake reference currently locks to
+#[derive(Debug, Clone)]
+pub struct FlakeLock {
+    /// Git revision, or the URL-safe NAR hash for flakes that aren't git repositories
+    pub rev: String,
+    /// Commit date, as an RFC 3339 timestamp
+    pub last_modified: String,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    revision: Option<String>,
+    #[serde(rename = "lastModified")]
+    last_modified: Option<i64>,
+    locked: Locked,
+}
+
+#[derive(Deserialize)]
+struct Locked {
+    rev: Option<String>,
+    #[serde(rename = "narHash")]
+    nar_hash: String,
+}
+
+impl Flake {
+    pub fn new(reference: &str, legacy_packages: bool) -> Self {
+        Flake {
+            reference: reference.to_string(),
+            legacy_packages,
+        }
+    }
+
+    /// Name the flake's databases are listed under in the index, see [`reference_name`]
+    pub fn name(&self) -> String {
+        reference_name(&self.reference)
+    }
+
+    pub async fn lock(&self, fixtures: Option<&Fixtures>) -> Result<FlakeLock> {
+        let output = match fixtures {
+            Some(fixtures) => fixtures.flake_metadata(&self.reference)?,
+            None => {
+                let output = nix()
+                    .args(["flake", "metadata", "--json"])
+                    .arg(&self.reference)
+                    .output()
+                    .await
+                    .context("Failed to execute nix")?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Failed to lock {}: {}",
+                        self.reference,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                output.stdout
+            }
+        };
+
+        let metadata: Metadata =
+            serde_json::from_slice(&output).context("Failed to parse flake metadata")?;
+        let last_modified = DateTime::from_timestamp(metadata.last_modified.unwrap_or_default(), 0)
+            .context("Invalid flake lastModified")?
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        Ok(FlakeLock {
+            rev: metadata
+                .revision
+                .or(metadata.locked.rev)
+                .unwrap_or_else(|| nar_hash_key(&metadata.locked.nar_hash)),
+            last_modified,
+        })
+    }
+
+    /// Store path index of the flake's packages on every system
+    ///
+    /// Systems the flake has no packages for evaluate to nothing, so any failure is an error.
+    pub async fn get_store(
+        &self,
+        systems: &[String],
+        fixtures: Option<&Fixtures>,
+    ) -> Result<HashMap<String, Store>> {
+        let mut store = HashMap::new();
+        for system in systems {
+            let output = self.eval(system, false, fixtures).await?;
+            info!("nix eval: got {} packages for {}", output.len(), system);
+            registry::add_stores(&mut store, system, &output);
+        }
+
+        info!("nix eval: got {} store paths", store.len());
+
+        Ok(store)
+    }
+
+    /// Packages and their metadata on `system`
+    pub async fn get_packages(
+        &self,
+        system: &str,
+        fixtures: Option<&Fixtures>,
+    ) -> Result<HashMap<String, Pkg>> {
+        let output = self.eval(system, true, fixtures).await?;
+        info!("nix eval: got {} packages for {}", output.len(), system);
+        Ok(registry::packages(output))
+    }
+
+    async fn eval(
+        &self,
+        system: &str,
+        with_meta: bool,
+        fixtures: Option<&Fixtures>,
+    ) -> Result<Registry> {
+        if let Some(fixtures) = fixtures {
+            return registry::parse(&fixtures.flake_registry(&self.reference, system)?);
+        }
+
+        // The reference is passed through the environment rather than spliced into the expression
+        let output = nix()
+            .env("LIBSNOW_FLAKE", &self.reference)
+            .env("NIXPKGS_ALLOW_UNFREE", "1")
+            .env("NIXPKGS_ALLOW_INSECURE", "1")
+            .args(["eval", "--impure", "--json", "--expr"])
+            .arg(expression(system, self.legacy_packages, with_meta))
+            .output()
+            .await
+            .context("Failed to execute nix")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to eval flake: {} ({}): {}",
+                self.reference,
+                system,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        registry::parse(&output.stdout)
+    }
+}
+
+/// A flake reference with every character other than `[A-Za-z0-9._-]` replaced by `_`, e.g.
+/// `github_snowfallorg_demo`, usable as a single path component or key
+pub fn reference_name(reference: &str) -> String {
+    reference
+        .chars()
+        .map(|x| match x {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '_' | '-' => x,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// An SRI hash such as `sha256-…` in URL-safe base64, as it names files and upload keys
+fn nar_hash_key(hash: &str) -> String {
+    hash.trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn nix() -> Command {
+    let mut command = Command::new("nix");
+    command.args(["--extra-experimental-features", "nix-command flakes"]);
+    command
+}
+
+/// genRegistry over a flake's outputs for `system`
+///
+/// registry.nix needs the nixpkgs lib, which is taken from the flake's `nixpkgs` input, from
+/// the flake itself when it is nixpkgs, or else from the `nixpkgs` flake registry entry.
+fn expression(system: &str, legacy_packages: bool, with_meta: bool) -> String {
+    format!(
+        r#"
+let
+  flake = builtins.getFlake (builtins.getEnv "LIBSNOW_FLAKE");
+  isLib = x: builtins.isAttrs x && x ? meta && x.meta ? availableOn;
+  lib =
+    if isLib (flake.inputs.nixpkgs.lib or null) then flake.inputs.nixpkgs.lib
+    else if isLib (flake.lib or null) then flake.lib
+    else (builtins.getFlake "nixpkgs").lib;
+  legacyPackages = if {legacy_packages} then flake.legacyPackages."{system}" or {{}} else {{}};
+  packages = flake.packages."{system}" or {{}};
+in
+  (import {REGISTRY} {{ inherit lib; withMeta = {with_meta}; }}).genRegistry "{system}" (legacyPackages // packages)
+"#
+    )
+}
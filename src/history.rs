@@ -0,0 +1,337 @@
+// Cross-revision package history
+//
+// Every processed release is merged into a single database, where each row of `versions` is an
+// interval of consecutive releases of a channel in which an attribute had the same version.
+//
+// When did `hello` change versions on nixos-unstable:
+//
+//     SELECT v.version, f.release, f.timestamp, l.release, l.timestamp
+//     FROM versions v
+//     JOIN releases f ON f.id = v.first_release
+//     JOIN releases l ON l.id = v.last_release
+//     WHERE v.channel = 'nixos/unstable' AND v.attribute = 'hello'
+//     ORDER BY f.timestamp;
+//
+// Every release in which a version appeared:
+//
+//     SELECT r.release FROM versions v
+//     JOIN releases f ON f.id = v.first_release
+//     JOIN releases l ON l.id = v.last_release
+//     JOIN releases r ON r.channel = v.channel AND r.timestamp BETWEEN f.timestamp AND l.timestamp
+//     WHERE v.id = ?;
+
+use anyhow::{Context, Result};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+use crate::{
+    fixtures::Fixtures,
+    revisions::source::Release,
+    s3::nix::{getmeta, getrevision},
+    Pkg,
+};
+
+/// Merge `release` of `channel` into the history database at `db`
+///
+/// Releases of a channel have to be added oldest first, adding one that is already present
+/// does nothing and adding one older than the newest present is an error.
+pub async fn update_history(
+    db: &str,
+    channel: &str,
+    release: &Release,
+    fixtures: Option<&Fixtures>,
+) -> Result<()> {
+    let data = getmeta(channel, release.name(), fixtures).await?;
+    let rev = getrevision(channel, release.name(), fixtures).await?;
+
+    info!("Got data, updating history");
+
+    let db = db.to_string();
+    let channel = channel.to_string();
+    let release = release.clone();
+    tokio::task::spawn_blocking(move || write_history(&db, &channel, &release, &rev, data))
+        .await??;
+
+    Ok(())
+}
+
+fn write_history(
+    db: &str,
+    channel: &str,
+    release: &Release,
+    rev: &str,
+    data: HashMap<String, Pkg>,
+) -> Result<()> {
+    let mut conn = Connection::open(db).context("Failed to open database")?;
+    merge(&mut conn, channel, release, rev, data)
+}
+
+fn merge(
+    conn: &mut Connection,
+    channel: &str,
+    release: &Release,
+    rev: &str,
+    data: HashMap<String, Pkg>,
+) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS "releases" (
+            "id"	INTEGER NOT NULL,
+            "channel"	TEXT NOT NULL,
+            "release"	TEXT NOT NULL,
+            "rev"	TEXT NOT NULL,
+            "timestamp"	TEXT NOT NULL,
+            UNIQUE("channel", "release"),
+            PRIMARY KEY("id")
+        );
+        CREATE TABLE IF NOT EXISTS "versions" (
+            "id"	INTEGER NOT NULL,
+            "channel"	TEXT NOT NULL,
+            "attribute"	TEXT NOT NULL,
+            "version"	TEXT NOT NULL,
+            "first_release"	INTEGER NOT NULL,
+            "last_release"	INTEGER NOT NULL,
+            FOREIGN KEY("first_release") REFERENCES "releases" ("id"),
+            FOREIGN KEY("last_release") REFERENCES "releases" ("id"),
+            PRIMARY KEY("id")
+        );
+        CREATE INDEX IF NOT EXISTS "idx_releases_timestamp" ON "releases" ("channel", "timestamp");
+        CREATE INDEX IF NOT EXISTS "idx_versions_attribute" ON "versions" ("attribute", "channel");
+        CREATE INDEX IF NOT EXISTS "idx_versions_last_release" ON "versions" ("channel", "last_release");
+        "#,
+    )
+    .context("Failed to create tables")?;
+
+    let tx = conn.transaction()?;
+
+    let exists = tx
+        .query_row(
+            r#"SELECT "id" FROM "releases" WHERE "channel" = ?1 AND "release" = ?2"#,
+            params![channel, release.name()],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?;
+    if exists.is_some() {
+        info!("{} is already in the history", release.name());
+        return Ok(());
+    }
+
+    let previous = tx
+        .query_row(
+            r#"SELECT "id", "release", "timestamp" FROM "releases" WHERE "channel" = ?1 ORDER BY "timestamp" DESC LIMIT 1"#,
+            params![channel],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)),
+        )
+        .optional()?;
+    // Intervals can only be extended forwards
+    if let Some((_, newest, timestamp)) = &previous {
+        if release.last_modified < *timestamp {
+            anyhow::bail!(
+                "{} is older than {}, the newest release of {} in the history",
+                release.name(),
+                newest,
+                channel
+            );
+        }
+    }
+
+    let id = tx
+        .prepare(
+            r#"INSERT INTO "releases" ("channel", "release", "rev", "timestamp") VALUES (?1, ?2, ?3, ?4)"#,
+        )?
+        .insert(params![channel, release.name(), rev, release.last_modified])?;
+
+    // Intervals that ended at the previous release, and may be extended by this one
+    let mut open: HashMap<String, (i64, String)> = HashMap::new();
+    if let Some((previous, _, _)) = previous {
+        let mut stmt = tx.prepare(
+            r#"SELECT "id", "attribute", "version" FROM "versions" WHERE "channel" = ?1 AND "last_release" = ?2"#,
+        )?;
+        let rows = stmt.query_map(params![channel, previous], |row| {
+            Ok((row.get(1)?, (row.get(0)?, row.get(2)?)))
+        })?;
+        for row in rows {
+            let (attribute, interval) = row?;
+            open.insert(attribute, interval);
+        }
+    }
+
+    {
+        let mut extend =
+            tx.prepare(r#"UPDATE "versions" SET "last_release" = ?1 WHERE "id" = ?2"#)?;
+        let mut insert = tx.prepare(
+            r#"INSERT INTO "versions" ("channel", "attribute", "version", "first_release", "last_release") VALUES (?1, ?2, ?3, ?4, ?4)"#,
+        )?;
+
+        let (mut extended, mut inserted) = (0, 0);
+        for pkg in data.values() {
+            match open.get(&pkg.attribute) {
+                Some((interval, version)) if *version == pkg.version => {
+                    extend.execute(params![id, interval])?;
+                    extended += 1;
+                }
+                _ => {
+                    insert.execute(params![channel, pkg.attribute, pkg.version, id])?;
+                    inserted += 1;
+                }
+            }
+        }
+
+        info!(
+            "History: {} unchanged, {} new or changed versions",
+            extended, inserted
+        );
+    }
+
+    tx.commit().context("Failed to commit database")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: &str = "nixos/unstable";
+
+    fn release(name: &str, day: u32) -> Release {
+        Release {
+            key: format!("{}/{}", CHANNEL, name),
+            last_modified: format!("2024-01-{:02}T00:00:00.000Z", day),
+        }
+    }
+
+    fn pkgs(versions: &[(&str, &str)]) -> HashMap<String, Pkg> {
+        versions
+            .iter()
+            .map(|(attribute, version)| {
+                let pkg = Pkg {
+                    attribute: attribute.to_string(),
+                    outputs: HashMap::new(),
+                    meta: Default::default(),
+                    pname: attribute.to_string(),
+                    version: version.to_string(),
+                };
+                (attribute.to_string(), pkg)
+            })
+            .collect()
+    }
+
+    fn add(conn: &mut Connection, release: &Release, versions: &[(&str, &str)]) -> Result<()> {
+        merge(conn, CHANNEL, release, "rev", pkgs(versions))
+    }
+
+    /// Version, first and last release of every interval of `attribute`, oldest first
+    fn intervals(conn: &Connection, attribute: &str) -> Vec<(String, String, String)> {
+        let mut stmt = conn
+            .prepare(
+                r#"
+                SELECT v.version, f.release, l.release FROM versions v
+                JOIN releases f ON f.id = v.first_release
+                JOIN releases l ON l.id = v.last_release
+                WHERE v.channel = ?1 AND v.attribute = ?2
+                ORDER BY f.timestamp
+                "#,
+            )
+            .unwrap();
+        stmt.query_map(params![CHANNEL, attribute], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+    }
+
+    fn interval(version: &str, first: &str, last: &str) -> (String, String, String) {
+        (version.to_string(), first.to_string(), last.to_string())
+    }
+
+    #[test]
+    fn unchanged_version_extends_interval() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(&mut conn, &release("a", 1), &[("hello", "2.12")]).unwrap();
+        add(&mut conn, &release("b", 2), &[("hello", "2.12")]).unwrap();
+
+        assert_eq!(intervals(&conn, "hello"), [interval("2.12", "a", "b")]);
+    }
+
+    #[test]
+    fn changed_version_starts_interval() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(&mut conn, &release("a", 1), &[("hello", "2.12")]).unwrap();
+        add(&mut conn, &release("b", 2), &[("hello", "2.13")]).unwrap();
+        add(&mut conn, &release("c", 3), &[("hello", "2.13")]).unwrap();
+
+        assert_eq!(
+            intervals(&conn, "hello"),
+            [interval("2.12", "a", "a"), interval("2.13", "b", "c")]
+        );
+    }
+
+    #[test]
+    fn reappearing_package_starts_interval() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(
+            &mut conn,
+            &release("a", 1),
+            &[("hello", "2.12"), ("steam", "1.0")],
+        )
+        .unwrap();
+        add(&mut conn, &release("b", 2), &[("steam", "1.0")]).unwrap();
+        add(
+            &mut conn,
+            &release("c", 3),
+            &[("hello", "2.12"), ("steam", "1.0")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            intervals(&conn, "hello"),
+            [interval("2.12", "a", "a"), interval("2.12", "c", "c")]
+        );
+        assert_eq!(intervals(&conn, "steam"), [interval("1.0", "a", "c")]);
+    }
+
+    #[test]
+    fn present_release_is_skipped() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(&mut conn, &release("a", 1), &[("hello", "2.12")]).unwrap();
+        add(&mut conn, &release("a", 1), &[("hello", "2.13")]).unwrap();
+
+        assert_eq!(intervals(&conn, "hello"), [interval("2.12", "a", "a")]);
+        let releases: i64 = conn
+            .query_row("SELECT COUNT(*) FROM releases", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(releases, 1);
+    }
+
+    #[test]
+    fn older_release_is_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(&mut conn, &release("b", 2), &[("hello", "2.13")]).unwrap();
+        assert!(add(&mut conn, &release("a", 1), &[("hello", "2.12")]).is_err());
+
+        assert_eq!(intervals(&conn, "hello"), [interval("2.13", "b", "b")]);
+    }
+
+    #[test]
+    fn channels_are_merged_separately() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        add(&mut conn, &release("b", 2), &[("hello", "2.13")]).unwrap();
+        let stable = Release {
+            key: "nixos/stable/a".to_string(),
+            last_modified: "2024-01-01T00:00:00.000Z".to_string(),
+        };
+        merge(
+            &mut conn,
+            "nixos/stable",
+            &stable,
+            "rev",
+            pkgs(&[("hello", "2.12")]),
+        )
+        .unwrap();
+
+        assert_eq!(intervals(&conn, "hello"), [interval("2.13", "b", "b")]);
+    }
+}
@@ -1,6 +1,17 @@
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
 
+use meta::{License, Link, Maintainer, OneOrMany, Platform};
+
+pub mod diff;
+pub mod feed;
+pub mod flake;
+pub mod fixtures;
+pub mod history;
+pub mod meta;
+pub mod nixpkgs;
+pub mod registry;
 pub mod revisions;
 pub mod s3;
 pub mod ddb;
@@ -8,30 +19,32 @@ pub mod ddb;
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Pkg {
     pub attribute: String,
-    // pub store: String,
+    /// Output name to store path, without the /nix/store/ prefix
+    #[serde(default)]
+    pub outputs: HashMap<String, String>,
     pub meta: MetaData,
     pub pname: String,
     pub version: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Default)]
 pub struct MetaData {
     // https://github.com/NixOS/nixpkgs/blob/master/doc/stdenv/meta.chapter.md
     pub description: Option<String>,
     #[serde(rename = "longDescription")]
     pub long_description: Option<String>,
     pub branch: Option<String>,
-    pub homepage: Option<Value>,
+    pub homepage: Option<OneOrMany<Link>>,
     #[serde(rename = "downloadPage")]
-    pub download_page: Option<Value>,
-    pub changelog: Option<Value>,
-    pub license: Option<Value>,
-    pub maintainers: Option<Value>,
+    pub download_page: Option<OneOrMany<Link>>,
+    pub changelog: Option<OneOrMany<Link>>,
+    pub license: Option<OneOrMany<License>>,
+    pub maintainers: Option<OneOrMany<Maintainer>>,
     #[serde(rename = "mainProgram")]
     pub main_program: Option<String>,
-    pub platforms: Option<Value>,
+    pub platforms: Option<Vec<Platform>>,
     #[serde(rename = "badPlatforms")]
-    pub bad_platforms: Option<Value>,
+    pub bad_platforms: Option<Vec<Platform>>,
     pub broken: Option<bool>,
     pub unfree: Option<bool>,
     pub insecure: Option<bool>,
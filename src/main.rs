@@ -1,18 +1,40 @@
-use std::fs;
-use std::io::Write;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    future::Future,
+    sync::Arc,
+};
 
 use anyhow::{Context, Result};
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::BehaviorVersion;
-use clap::{Parser, Subcommand};
-use libsnow_generators::ddb::batch_put::batch_store_put;
+use clap::{Parser, Subcommand, ValueEnum};
+use libsnow_generators::ddb::sink::{DynamoDbSink, NdjsonSink, SqliteSink, StoreSink};
+use libsnow_generators::fixtures::Fixtures;
 use libsnow_generators::revisions::add_failed_revision;
+use libsnow_generators::revisions::source::{
+    LocalMirror, Manifest, Release, RevisionSource, S3Listing, NIX_RELEASES,
+};
 use libsnow_generators::{
-    ddb::nix::get_store,
-    revisions::{get_revisions, update_markers},
-    s3::db::create_db,
+    ddb::{
+        nix::{fetch_nixpkgs, get_packages, get_store},
+        Store,
+    },
+    diff::{diff, diff_releases, Diff},
+    feed::{update_feed, FeedEntry},
+    flake::Flake,
+    history::update_history,
+    nixpkgs::{NixpkgsSource, GITHUB_TARBALL},
+    revisions::{
+        filter::ReleaseFilter, get_release, get_revisions, read_marker, update_marker,
+        write_atomic, FEED_LAST, HISTORY_LAST, LAST,
+    },
+    s3::db::{create_db, publish_db, Compression},
+    s3::nix::{getmeta, getrevision},
+    s3::upload::{LocalTarget, S3Target, UploadTarget},
 };
 use log::*;
+use tokio::{sync::Semaphore, task::JoinSet};
 
 #[derive(Subcommand, Debug)]
 enum Commands {
@@ -20,14 +42,188 @@ enum Commands {
         #[arg(short, long)]
         /// Upload to S3
         upload: bool,
+        #[arg(long, value_enum, default_value = "s3")]
+        /// Where to upload databases to
+        target: Target,
         #[arg(short, long, default_value = "libsnow")]
         /// S3 Bucket to upload to
         bucket: String,
+        #[arg(long)]
+        /// Endpoint URL of the S3-compatible object store
+        endpoint_url: Option<String>,
+        #[arg(long)]
+        /// Use path-style addressing with the S3-compatible object store
+        path_style: bool,
+        #[arg(long)]
+        /// Directory to publish to for the local target
+        output_dir: Option<String>,
+        #[arg(long, default_value_t = 11, value_parser = clap::value_parser!(u32).range(0..=11))]
+        /// Brotli compression quality
+        brotli_quality: u32,
+        #[arg(long, default_value_t = 24, value_parser = clap::value_parser!(u32).range(10..=24))]
+        /// Brotli window size, as a base 2 logarithm
+        brotli_window: u32,
+        #[arg(short, long, default_value_t = 4)]
+        /// Number of channels processed at once
+        jobs: usize,
+        #[arg(long)]
+        /// Only build the database of this release, e.g. nixos/unstable/nixos-24.05pre564493.b0d36bd0a420, leaving markers untouched
+        release: Option<String>,
+        #[arg(long, conflicts_with = "release")]
+        /// Only build the database of this flake's packages, e.g. github:snowfallorg/nix-software-center
+        flake: Option<String>,
+        #[arg(long, requires = "flake")]
+        /// Also include the flake's legacyPackages
+        legacy_packages: bool,
+        #[arg(long, conflicts_with_all = ["release", "flake"], requires = "name")]
+        /// Only build the database of this nixpkgs git revision, evaluated locally instead of from packages.json
+        rev: Option<String>,
+        #[arg(long, default_value = GITHUB_TARBALL)]
+        /// Nixpkgs to evaluate with --rev: a tarball URL or flake reference where {rev} is replaced by the full git revision, or a local checkout
+        nixpkgs: NixpkgsSource,
+        #[arg(long, requires = "rev")]
+        /// Index entry the --rev database is listed under, e.g. my-fork; it never becomes latest
+        name: Option<String>,
+        #[arg(long, default_value = "x86_64-linux")]
+        /// System to evaluate packages for with --flake or --rev
+        system: String,
+    },
+    History {
+        #[arg(short, long, default_value = "history.db")]
+        /// History database to update
+        database: String,
     },
     Ddb {
+        #[arg(long, value_enum, default_value = "dynamodb")]
+        /// Where to write the store path index
+        sink: Sink,
         #[arg(short, long)]
         /// DynamoDB table to upload to
-        table: String,
+        table: Option<String>,
+        #[arg(short, long)]
+        /// File to write to for the sqlite and ndjson sinks
+        output: Option<String>,
+        #[arg(
+            long = "system",
+            value_delimiter = ',',
+            default_value = "x86_64-linux,aarch64-linux,x86_64-darwin,aarch64-darwin"
+        )]
+        /// Systems to evaluate packages for
+        systems: Vec<String>,
+        #[arg(long, default_value = GITHUB_TARBALL)]
+        /// Nixpkgs to evaluate: a tarball URL or flake reference where {rev} is replaced by the full git revision, or a local checkout
+        nixpkgs: NixpkgsSource,
+        #[arg(long, default_value_t = 4)]
+        /// Maximum number of batch writes in flight at once
+        concurrency: usize,
+        #[arg(short, long, default_value_t = 1)]
+        /// Number of channels evaluated at once, each evaluation can take several GB of memory
+        jobs: usize,
+        #[arg(long, conflicts_with = "rev")]
+        /// Only index this release, e.g. nixos/unstable/nixos-24.05pre564493.b0d36bd0a420, leaving markers untouched
+        release: Option<String>,
+        #[arg(long)]
+        /// Only index this nixpkgs git revision, in full, leaving markers untouched
+        rev: Option<String>,
+        #[arg(long, conflicts_with_all = ["release", "rev"])]
+        /// Only index this flake's packages, e.g. github:snowfallorg/nix-software-center
+        flake: Option<String>,
+        #[arg(long, requires = "flake")]
+        /// Also include the flake's legacyPackages
+        legacy_packages: bool,
+    },
+    /// Generate an Atom feed of new releases per channel
+    Feed {
+        #[arg(short, long, default_value = "feeds")]
+        /// Directory to write feeds to, as {channel}.atom
+        output: String,
+        #[arg(long, default_value_t = 50)]
+        /// Maximum number of releases kept in each feed
+        max_entries: usize,
+    },
+    /// Compare the package sets of two releases of a channel
+    Diff {
+        /// Channel, e.g. nixos/unstable
+        channel: String,
+        /// Older release, e.g. nixos-24.05pre564493.b0d36bd0a420
+        from: String,
+        /// Newer release
+        to: String,
+        #[arg(long)]
+        /// Print the report as JSON
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Target {
+    /// AWS S3 bucket
+    S3,
+    /// S3-compatible object store, such as MinIO
+    S3Compatible,
+    /// Local directory
+    Local,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Sink {
+    /// DynamoDB table
+    Dynamodb,
+    /// Local SQLite database
+    Sqlite,
+    /// Newline-delimited JSON file
+    Ndjson,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Source {
+    /// Public S3 bucket listing
+    S3,
+    /// Local directory mirror of releases
+    Mirror,
+    /// Static JSON manifest of releases
+    Manifest,
+}
+
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    #[arg(long, value_enum, default_value = "s3")]
+    /// Where to discover channel releases
+    source: Source,
+    #[arg(long)]
+    /// Bucket URL, mirror directory or manifest file for the release source
+    source_location: Option<String>,
+    #[arg(long = "channel", value_delimiter = ',')]
+    /// Only process channels matching these globs, e.g. nixos/*
+    channels: Vec<String>,
+    #[arg(long = "exclude-channel", value_delimiter = ',')]
+    /// Skip channels matching these globs
+    exclude_channels: Vec<String>,
+    #[arg(long)]
+    /// Process releases from this date on (YYYY-MM-DD or RFC 3339), ignoring and keeping markers
+    since: Option<String>,
+    #[arg(long)]
+    /// Process releases up to this date (YYYY-MM-DD or RFC 3339), ignoring and keeping markers
+    until: Option<String>,
+    #[arg(long)]
+    /// Maximum number of releases processed per channel
+    limit: Option<usize>,
+}
+
+impl SourceArgs {
+    fn filter(&self) -> Result<ReleaseFilter> {
+        ReleaseFilter::new(
+            &self.channels,
+            &self.exclude_channels,
+            self.since.as_deref(),
+            self.until.as_deref(),
+            self.limit,
+        )
+    }
+
+    /// Whether releases are picked by date, which leaves markers untouched
+    fn backfill(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
     }
 }
 
@@ -36,6 +232,11 @@ struct Args {
     #[arg(short, long, default_value = "./processed")]
     /// Directory where processed markers are stored
     processed: String,
+    #[command(flatten)]
+    source: SourceArgs,
+    #[arg(long)]
+    /// Read releases, package metadata and evaluations from a fixtures directory instead of the network and Nix
+    offline: Option<String>,
     #[arg(short, long)]
     /// Verbose logging
     verbose: bool,
@@ -56,99 +257,666 @@ async fn main() -> Result<()> {
 
     info!("Args: {:#?}", args);
 
+    let fixtures = args.offline.as_deref().map(Fixtures::new);
+    let move_markers = !args.source.backfill();
+
+    match args.command {
+        Commands::S3 {
+            upload,
+            target,
+            bucket,
+            endpoint_url,
+            path_style,
+            output_dir,
+            brotli_quality,
+            brotli_window,
+            jobs,
+            release,
+            flake,
+            legacy_packages,
+            rev,
+            nixpkgs,
+            name,
+            system,
+        } => {
+            let dbs = match (flake, rev) {
+                (Some(flake), _) => Dbs::Flake(Flake::new(&flake, legacy_packages), system),
+                (_, Some(rev)) => Dbs::Rev {
+                    rev,
+                    nixpkgs,
+                    name: name.context("--name is required with --rev")?,
+                    system,
+                },
+                _ => Dbs::Channels(
+                    discover(
+                        &args.processed,
+                        &args.source,
+                        release.as_deref(),
+                        LAST,
+                        fixtures.as_ref(),
+                    )
+                    .await?,
+                ),
+            };
+            let move_markers = move_markers && release.is_none();
+            let compression = Compression {
+                quality: brotli_quality,
+                window: brotli_window,
+            };
+            let fixtures = fixtures.as_ref();
+            match target {
+                _ if !upload => {
+                    build_dbs(
+                        None::<LocalTarget>,
+                        &args.processed,
+                        dbs,
+                        fixtures,
+                        compression,
+                        jobs,
+                        move_markers,
+                    )
+                    .await?;
+                }
+                Target::S3 => {
+                    if fixtures.is_some() {
+                        anyhow::bail!("--offline needs a local target to upload");
+                    }
+                    let client = aws_sdk_s3::Client::new(&aws_config().await);
+                    let target = S3Target::new(client, &bucket);
+                    build_dbs(
+                        Some(target),
+                        &args.processed,
+                        dbs,
+                        fixtures,
+                        compression,
+                        jobs,
+                        move_markers,
+                    )
+                    .await?;
+                }
+                Target::S3Compatible => {
+                    if fixtures.is_some() {
+                        anyhow::bail!("--offline needs a local target to upload");
+                    }
+                    let endpoint_url = endpoint_url
+                        .context("--endpoint-url is required for an s3-compatible target")?;
+                    let target = S3Target::compatible(
+                        &aws_config().await,
+                        &endpoint_url,
+                        path_style,
+                        &bucket,
+                    );
+                    build_dbs(
+                        Some(target),
+                        &args.processed,
+                        dbs,
+                        fixtures,
+                        compression,
+                        jobs,
+                        move_markers,
+                    )
+                    .await?;
+                }
+                Target::Local => {
+                    let output_dir =
+                        output_dir.context("--output-dir is required for a local target")?;
+                    let target = LocalTarget::new(&output_dir);
+                    build_dbs(
+                        Some(target),
+                        &args.processed,
+                        dbs,
+                        fixtures,
+                        compression,
+                        jobs,
+                        move_markers,
+                    )
+                    .await?;
+                }
+            }
+        }
+        Commands::History { database } => {
+            if !move_markers {
+                anyhow::bail!("History needs releases in order, --since and --until can't be used");
+            }
+            let revision = discover(
+                &args.processed,
+                &args.source,
+                None,
+                HISTORY_LAST,
+                fixtures.as_ref(),
+            )
+            .await?;
+            build_history(&database, &args.processed, &revision, fixtures.as_ref()).await?;
+        }
+        Commands::Ddb {
+            sink,
+            table,
+            output,
+            systems,
+            nixpkgs,
+            concurrency,
+            jobs,
+            release,
+            rev,
+            flake,
+            legacy_packages,
+        } => {
+            let eval = StoreEval {
+                systems: &systems,
+                nixpkgs: &nixpkgs,
+                fixtures: fixtures.as_ref(),
+            };
+            let stores = match (flake, rev, release) {
+                (Some(flake), _, _) => Stores::Flake(Flake::new(&flake, legacy_packages)),
+                (None, Some(rev), _) => Stores::Rev(rev),
+                (None, None, Some(release)) => {
+                    let (channel, name) = release
+                        .trim_end_matches('/')
+                        .rsplit_once('/')
+                        .with_context(|| {
+                            format!("Expected {{channel}}/{{release}}, got {}", release)
+                        })?;
+                    Stores::Rev(getrevision(channel, name, fixtures.as_ref()).await?)
+                }
+                (None, None, None) if nixpkgs.is_fixed() => {
+                    // Every pending release would be indexed from the same tree
+                    anyhow::bail!(
+                        "--nixpkgs needs {{rev}} to index channels, or else --rev, --release or --flake"
+                    );
+                }
+                (None, None, None) => Stores::Channels(
+                    discover(&args.processed, &args.source, None, LAST, fixtures.as_ref()).await?,
+                ),
+            };
+            match sink {
+                Sink::Dynamodb => {
+                    if fixtures.is_some() {
+                        anyhow::bail!("--offline needs a sqlite or ndjson sink");
+                    }
+                    let client = aws_sdk_dynamodb::Client::new(&aws_config().await);
+                    let table = table.context("--table is required for a dynamodb sink")?;
+                    let sink = DynamoDbSink::new(client, &table, concurrency);
+                    index_stores(sink, stores, &args.processed, &eval, jobs, move_markers).await?;
+                }
+                Sink::Sqlite => {
+                    let sink = SqliteSink::new(&output.context("--output is required")?);
+                    index_stores(sink, stores, &args.processed, &eval, jobs, move_markers).await?;
+                }
+                Sink::Ndjson => {
+                    let sink = NdjsonSink::new(&output.context("--output is required")?);
+                    index_stores(sink, stores, &args.processed, &eval, jobs, move_markers).await?;
+                }
+            }
+        }
+        Commands::Feed {
+            output,
+            max_entries,
+        } => {
+            if !move_markers {
+                anyhow::bail!("Feeds need releases in order, --since and --until can't be used");
+            }
+            let revision = discover(
+                &args.processed,
+                &args.source,
+                None,
+                FEED_LAST,
+                fixtures.as_ref(),
+            )
+            .await?;
+            build_feeds(
+                &output,
+                max_entries,
+                &args.processed,
+                &revision,
+                fixtures.as_ref(),
+            )
+            .await?;
+        }
+        Commands::Diff {
+            channel,
+            from,
+            to,
+            json,
+        } => {
+            let diff = diff_releases(&channel, &from, &to, fixtures.as_ref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{}", diff);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn discover(
+    processed: &str,
+    args: &SourceArgs,
+    release: Option<&str>,
+    marker: &str,
+    fixtures: Option<&Fixtures>,
+) -> Result<HashMap<String, Vec<Release>>> {
+    let filter = args.filter()?;
+    let revision = if let Some(fixtures) = fixtures {
+        select(
+            processed,
+            &S3Listing::offline(fixtures.clone()),
+            &filter,
+            release,
+            marker,
+        )
+        .await
+    } else {
+        match args.source {
+            Source::S3 => {
+                let listing =
+                    S3Listing::new(args.source_location.as_deref().unwrap_or(NIX_RELEASES));
+                select(processed, &listing, &filter, release, marker).await
+            }
+            Source::Mirror => {
+                let location = args
+                    .source_location
+                    .as_deref()
+                    .context("--source-location is required for a mirror source")?;
+                select(
+                    processed,
+                    &LocalMirror::new(location),
+                    &filter,
+                    release,
+                    marker,
+                )
+                .await
+            }
+            Source::Manifest => {
+                let location = args
+                    .source_location
+                    .as_deref()
+                    .context("--source-location is required for a manifest source")?;
+                select(
+                    processed,
+                    &Manifest::new(location),
+                    &filter,
+                    release,
+                    marker,
+                )
+                .await
+            }
+        }
+    }
+    .context("Failed to get revisions")?;
+
+    info!("Got revisions: {:#?}", revision);
+
+    Ok(revision)
+}
+
+/// Either the single `release` asked for, or everything past `marker`
+async fn select(
+    processed: &str,
+    source: &impl RevisionSource,
+    filter: &ReleaseFilter,
+    release: Option<&str>,
+    marker: &str,
+) -> Result<HashMap<String, Vec<Release>>> {
+    match release {
+        Some(release) => get_release(source, release).await,
+        None => get_revisions(processed, source, filter, marker).await,
+    }
+}
+
+async fn aws_config() -> aws_config::SdkConfig {
     let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::defaults(BehaviorVersion::latest())
+    aws_config::defaults(BehaviorVersion::latest())
         .region(region_provider)
         .retry_config(aws_config::retry::RetryConfig::adaptive())
         .load()
-        .await;
-
-    let revision = get_revisions(&args.processed)
         .await
-        .expect("Failed to get revisions");
+}
 
-    info!("Got revisions: {:#?}", revision);
+/// Run `work` on the pending releases of every channel, at most `jobs` channels at a time
+///
+/// Releases of a channel are still handled in order by `work`, so its marker only moves forward.
+async fn per_channel<F, Fut>(
+    revision: HashMap<String, Vec<Release>>,
+    jobs: usize,
+    work: F,
+) -> Result<()>
+where
+    F: Fn(String, Vec<Release>) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut tasks = JoinSet::new();
 
-    match args.command {
-        Commands::S3 { upload, bucket } => {
-            let client = aws_sdk_s3::Client::new(&config);
-            for (i, (channel, revs)) in revision.iter().enumerate() {
-                for (j, r) in revs.iter().enumerate() {
-                    info!(
-                        "Revision: {} ({}/{}) ({}/{})",
-                        r,
-                        j + 1,
-                        revs.len(),
-                        i + 1,
-                        revision.len()
-                    );
-                    create_db(&client, channel, r, upload, &bucket).await?;
+    for (channel, revs) in revision {
+        let semaphore = semaphore.clone();
+        let work = work(channel, revs);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            work.await
+        });
+    }
+
+    // Returning drops the remaining tasks, which aborts them
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+/// What the s3 command builds databases of
+enum Dbs {
+    /// Pending releases of every channel
+    Channels(HashMap<String, Vec<Release>>),
+    /// Packages of a flake on a system
+    Flake(Flake, String),
+    /// Packages of a nixpkgs revision on a system, listed in the index under `name`
+    Rev {
+        rev: String,
+        nixpkgs: NixpkgsSource,
+        name: String,
+        system: String,
+    },
+}
+
+async fn build_dbs(
+    target: Option<impl UploadTarget + Send + Sync + 'static>,
+    processed: &str,
+    dbs: Dbs,
+    fixtures: Option<&Fixtures>,
+    compression: Compression,
+    jobs: usize,
+    move_markers: bool,
+) -> Result<()> {
+    let revision = match dbs {
+        Dbs::Channels(revision) => revision,
+        Dbs::Flake(flake, system) => {
+            let lock = flake.lock(fixtures).await?;
+            info!("Flake: {} at {}", flake.reference, lock.rev);
+            let data = flake.get_packages(&system, fixtures).await?;
+            return publish_db(
+                target.as_ref(),
+                &flake.name(),
+                &lock.rev,
+                &lock.rev,
+                Some(&lock.last_modified),
+                data,
+                compression,
+            )
+            .await;
+        }
+        Dbs::Rev {
+            rev,
+            nixpkgs,
+            name,
+            system,
+        } => {
+            info!("Revision: {} of {}", rev, name);
+            let data = get_packages(&rev, &system, &nixpkgs, fixtures).await?;
+            // There is no release date for an arbitrary revision
+            return publish_db(target.as_ref(), &name, &rev, &rev, None, data, compression).await;
+        }
+    };
+
+    let target = target.map(Arc::new);
+    per_channel(revision, jobs, |channel, revs| {
+        let target = target.clone();
+        let processed = processed.to_string();
+        let fixtures = fixtures.cloned();
+        async move {
+            for (j, release) in revs.iter().enumerate() {
+                let r = release.name();
+                info!("Revision: {} ({}/{}) of {}", r, j + 1, revs.len(), channel);
+                create_db(
+                    target.as_deref(),
+                    &channel,
+                    release,
+                    fixtures.as_ref(),
+                    compression,
+                )
+                .await?;
+                if move_markers {
+                    update_marker(&processed, &channel, LAST, r)?;
                 }
             }
+            Ok(())
+        }
+    })
+    .await
+}
+
+async fn build_history(
+    database: &str,
+    processed: &str,
+    revision: &HashMap<String, Vec<Release>>,
+    fixtures: Option<&Fixtures>,
+) -> Result<()> {
+    for (i, (channel, revs)) in revision.iter().enumerate() {
+        for (j, release) in revs.iter().enumerate() {
+            let r = release.name();
+            info!(
+                "Revision: {} ({}/{}) ({}/{})",
+                r,
+                j + 1,
+                revs.len(),
+                i + 1,
+                revision.len()
+            );
+            update_history(database, channel, release, fixtures).await?;
+            update_marker(processed, channel, HISTORY_LAST, r)?;
         }
-        Commands::Ddb { table } => {
-            let client = aws_sdk_dynamodb::Client::new(&config);
-            for (i, (channel, revs)) in revision.iter().enumerate() {
-                for (j, r) in revs.iter().enumerate() {
-                    info!(
-                        "Revision: {} ({}/{}) ({}/{})",
-                        r,
-                        j + 1,
-                        revs.len(),
-                        i + 1,
-                        revision.len()
+    }
+
+    Ok(())
+}
+
+async fn build_feeds(
+    output: &str,
+    max_entries: usize,
+    processed: &str,
+    revision: &HashMap<String, Vec<Release>>,
+    fixtures: Option<&Fixtures>,
+) -> Result<()> {
+    for (i, (channel, revs)) in revision.iter().enumerate() {
+        if revs.is_empty() {
+            continue;
+        }
+
+        // Older releases would drop straight out of the feed, so they only serve as the first
+        // release to compare against
+        let skip = revs.len().saturating_sub(max_entries);
+        let mut previous = match skip {
+            0 => read_marker(processed, channel, FEED_LAST)?,
+            _ => revs[skip - 1].name().to_string(),
+        };
+        // A new or expired marker has nothing to compare the first release to
+        let mut previous_meta = match previous.as_str() {
+            "" => None,
+            _ => match getmeta(channel, &previous, fixtures).await {
+                Ok(meta) => Some(meta),
+                Err(e) if is_not_found(&e) => {
+                    warn!("No packages of {} to compare to: {:#}", previous, e);
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        for (j, release) in revs.iter().enumerate().skip(skip) {
+            let r = release.name();
+            info!(
+                "Revision: {} ({}/{}) ({}/{})",
+                r,
+                j + 1,
+                revs.len(),
+                i + 1,
+                revision.len()
+            );
+            let meta = getmeta(channel, r, fixtures).await?;
+            let rev = getrevision(channel, r, fixtures).await?;
+            let entry = match &previous_meta {
+                Some(previous_meta) => {
+                    FeedEntry::new(Some(&previous), release, &rev, &diff(previous_meta, &meta))
+                }
+                None => FeedEntry::new(None, release, &rev, &Diff::default()),
+            };
+            update_feed(processed, output, channel, entry, max_entries)?;
+            update_marker(processed, channel, FEED_LAST, r)?;
+
+            previous = r.to_string();
+            previous_meta = Some(meta);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `e` comes from a file or URL that doesn't exist, rather than a failure to read it
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|x| {
+        x.downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            == Some(reqwest::StatusCode::NOT_FOUND)
+            || x.downcast_ref::<std::io::Error>()
+                .is_some_and(|x| x.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+/// What the ddb command indexes
+enum Stores {
+    /// Pending releases of every channel
+    Channels(HashMap<String, Vec<Release>>),
+    /// A single nixpkgs revision
+    Rev(String),
+    /// Packages of a flake
+    Flake(Flake),
+}
+
+async fn index_stores(
+    sink: impl StoreSink + Send + Sync + 'static,
+    stores: Stores,
+    processed: &str,
+    eval: &StoreEval<'_>,
+    jobs: usize,
+    move_markers: bool,
+) -> Result<()> {
+    match stores {
+        Stores::Channels(revision) => {
+            store_index(sink, processed, revision, eval, jobs, move_markers).await
+        }
+        Stores::Rev(rev) => store_rev(&sink, &rev, eval).await,
+        Stores::Flake(flake) => store_flake(&sink, &flake, eval).await,
+    }
+}
+
+/// How store paths are evaluated for the ddb command
+struct StoreEval<'a> {
+    systems: &'a [String],
+    nixpkgs: &'a NixpkgsSource,
+    fixtures: Option<&'a Fixtures>,
+}
+
+async fn store_index(
+    sink: impl StoreSink + Send + Sync + 'static,
+    processed: &str,
+    revision: HashMap<String, Vec<Release>>,
+    eval: &StoreEval<'_>,
+    jobs: usize,
+    move_markers: bool,
+) -> Result<()> {
+    let sink = Arc::new(sink);
+    per_channel(revision, jobs, |channel, revs| {
+        let sink = sink.clone();
+        let processed = processed.to_string();
+        let systems = eval.systems.to_vec();
+        let nixpkgs = eval.nixpkgs.clone();
+        let fixtures = eval.fixtures.cloned();
+        async move {
+            for (j, release) in revs.iter().enumerate() {
+                let r = release.name();
+                info!("Revision: {} ({}/{}) of {}", r, j + 1, revs.len(), channel);
+                // Stop before the marker moves, as the release is still pending
+                let rev = getrevision(&channel, r, fixtures.as_ref()).await?;
+                let nixpath = fetch_nixpkgs(&rev, &nixpkgs, fixtures.as_ref()).await?;
+                let storeset = get_store(&rev, &nixpath, &systems, fixtures.as_ref()).await?;
+
+                if let Some(storeset) = storeset {
+                    // Read processed
+                    let prevpaths =
+                        fs::read_to_string(format!("{}/{}/store-paths", processed, channel))
+                            .unwrap_or_default();
+                    let paths = prevpaths.split("\n").collect::<HashSet<&str>>();
+
+                    let new_storeset = storeset
+                        .iter()
+                        .filter(|(k, _v)| !paths.contains(k.as_str()))
+                        .map(|(k, v)| (k.to_string(), v.clone()))
+                        .collect::<HashMap<String, _>>();
+
+                    debug!(
+                        "Total paths: {}. New paths: {}",
+                        paths.len(),
+                        new_storeset.len()
                     );
-                    let storeset =
-                        get_store(r.split('.').last().context("Failed to get revision")?).await;
-
-                    if let Ok(storeset) = storeset {
-                        // Read processed
-                        let prevpaths = fs::read_to_string(format!(
-                            "{}/{}/store-paths",
-                            args.processed, channel
-                        ))
-                        .unwrap_or_default();
-                        let paths = prevpaths.split("\n").collect::<Vec<&str>>();
-
-                        // Write to processed
-                        let mut file = fs::File::create(format!(
-                            "{}/{}/store-paths",
-                            args.processed, channel
-                        ))?;
-
-                        let new_storeset = storeset
-                            .iter()
-                            .filter(|(k, _v)| !paths.contains(&k.as_str()))
-                            .map(|(k, v)| (k.to_string(), v.clone()))
-                            .collect::<std::collections::HashMap<String, _>>();
-
-                        debug!(
-                            "Total paths: {}. New paths: {}",
-                            paths.len(),
-                            new_storeset.len()
-                        );
-
-                        batch_store_put(&client, &new_storeset, &table).await?;
-
-                        file.write_all(
+
+                    let report = sink.put(&new_storeset).await?;
+
+                    // Write to processed, unless backfilling older releases
+                    if move_markers {
+                        write_atomic(
+                            &format!("{}/{}/store-paths", processed, channel),
+                            // Leave out anything that never landed so the next run retries it
                             storeset
                                 .keys()
+                                .filter(|k| !report.unprocessed.contains(k))
                                 .map(String::to_string)
                                 .collect::<Vec<_>>()
                                 .join("\n")
                                 .as_bytes(),
                         )
-                        .expect("Failed to write to store-paths file");
-                    } else {
-                        error!("Failed to eval revision: {}", r);
-                        add_failed_revision(&format!("{}/{}", &args.processed, channel), r)?;
+                        .context("Failed to write to store-paths file")?;
                     }
+                } else {
+                    error!("Failed to eval revision: {}", r);
+                    add_failed_revision(&format!("{}/{}", processed, channel), r)?;
+                }
+                if move_markers {
+                    update_marker(&processed, &channel, LAST, r)?;
                 }
             }
+            Ok(())
         }
-    }
+    })
+    .await
+}
+
+/// Index every store path of a single nixpkgs revision, without reading or moving markers
+async fn store_rev(sink: &impl StoreSink, rev: &str, eval: &StoreEval<'_>) -> Result<()> {
+    info!("Revision: {}", rev);
+    let nixpath = fetch_nixpkgs(rev, eval.nixpkgs, eval.fixtures).await?;
+    let storeset = get_store(rev, &nixpath, eval.systems, eval.fixtures)
+        .await?
+        .with_context(|| format!("Failed to eval revision: {}", rev))?;
+    put_all(sink, &storeset).await
+}
 
-    let _ = update_markers(&args.processed, revision)?;
+/// Index every store path of a flake's packages
+async fn store_flake(sink: &impl StoreSink, flake: &Flake, eval: &StoreEval<'_>) -> Result<()> {
+    info!("Flake: {}", flake.reference);
+    let storeset = flake.get_store(eval.systems, eval.fixtures).await?;
+    put_all(sink, &storeset).await
+}
+
+/// Write a whole store path index, failing if any of it never lands
+async fn put_all(sink: &impl StoreSink, storeset: &HashMap<String, Store>) -> Result<()> {
+    let report = sink.put(storeset).await?;
+    if !report.unprocessed.is_empty() {
+        anyhow::bail!(
+            "{} store paths were never written",
+            report.unprocessed.len()
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,367 @@
+// Typed views of the `meta` attributes that nixpkgs has allowed to take several shapes over time
+// https://github.com/NixOS/nixpkgs/blob/master/doc/stdenv/meta.chapter.md
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A value that may be given either on its own or as a list
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    // Lists are tried first, so that catch-all item types don't swallow them
+    Many(Vec<T>),
+    One(T),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        match self {
+            OneOrMany::Many(x) => x.iter(),
+            OneOrMany::One(x) => std::slice::from_ref(x).iter(),
+        }
+    }
+}
+
+/// `meta.license`, either an attrset from `lib.licenses` or a bare SPDX identifier
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum License {
+    Spdx(String),
+    Info(LicenseInfo),
+    /// Anything else found in historical revisions, kept as-is
+    Other(Value),
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Hash, Default)]
+pub struct LicenseInfo {
+    #[serde(rename = "spdxId", skip_serializing_if = "Option::is_none")]
+    pub spdx_id: Option<String>,
+    #[serde(rename = "shortName", skip_serializing_if = "Option::is_none")]
+    pub short_name: Option<String>,
+    #[serde(rename = "fullName", skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redistributable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+}
+
+impl License {
+    /// Normalize a bare SPDX identifier into a [`LicenseInfo`], if there is anything to
+    /// identify the license by
+    pub fn info(&self) -> Option<LicenseInfo> {
+        let info = match self {
+            License::Spdx(spdx_id) => LicenseInfo {
+                spdx_id: Some(spdx_id.to_string()),
+                ..Default::default()
+            },
+            License::Info(info) => info.clone(),
+            License::Other(_) => return None,
+        };
+        let identified = info.spdx_id.is_some()
+            || info.short_name.is_some()
+            || info.full_name.is_some()
+            || info.url.is_some();
+        identified.then_some(info)
+    }
+}
+
+/// An entry of `meta.maintainers`, either an attrset from `lib.maintainers` or, in older
+/// revisions, a `"Name <email>"` string
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Maintainer {
+    Legacy(String),
+    Info(MaintainerInfo),
+    /// Anything else found in historical revisions, kept as-is
+    Other(Value),
+}
+
+/// The fields of a maintainer attrset that are indexed
+///
+/// Attrsets with other fields, like `keys`, deserialize as [`Maintainer::Other`] so that they
+/// round-trip unchanged.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MaintainerInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github: Option<String>,
+    #[serde(rename = "githubId", skip_serializing_if = "Option::is_none")]
+    pub github_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<String>,
+}
+
+impl Maintainer {
+    /// Normalize into a [`MaintainerInfo`], if there is anything to identify the maintainer by
+    ///
+    /// Legacy strings are split into `name` and `email`, and attrsets with fields of unexpected
+    /// types keep the fields that can be read.
+    pub fn info(&self) -> Option<MaintainerInfo> {
+        let info = match self {
+            Maintainer::Legacy(legacy) => match legacy.split_once('<') {
+                Some((name, email)) => MaintainerInfo {
+                    name: Some(name.trim().to_string()),
+                    email: Some(email.trim_end_matches('>').trim().to_string()),
+                    ..Default::default()
+                },
+                None => MaintainerInfo {
+                    name: Some(legacy.trim().to_string()),
+                    ..Default::default()
+                },
+            },
+            Maintainer::Info(info) => info.clone(),
+            Maintainer::Other(Value::Object(fields)) => {
+                let string = |field: &str| fields.get(field)?.as_str().map(str::to_string);
+                MaintainerInfo {
+                    name: string("name"),
+                    github: string("github"),
+                    // e.g. a githubId given as a string
+                    github_id: fields
+                        .get("githubId")
+                        .and_then(|x| x.as_u64().or_else(|| x.as_str()?.parse().ok())),
+                    email: string("email"),
+                    matrix: string("matrix"),
+                }
+            }
+            Maintainer::Other(_) => return None,
+        };
+        let identified = info.name.is_some()
+            || info.github.is_some()
+            || info.github_id.is_some()
+            || info.email.is_some()
+            || info.matrix.is_some();
+        identified.then_some(info)
+    }
+}
+
+/// An entry of `meta.homepage`, `meta.downloadPage` or `meta.changelog`
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Link {
+    Url(String),
+    /// Anything else found in historical revisions, kept as-is
+    Other(Value),
+}
+
+/// An entry of `meta.platforms` or `meta.badPlatforms`
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Platform {
+    /// A system double such as `x86_64-linux`
+    System(String),
+    /// A `lib.systems.inspect` pattern such as `{ kernel.name = "darwin"; }`
+    Pattern(Value),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MetaData;
+    use serde_json::json;
+
+    fn meta(value: Value) -> MetaData {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn infos(meta: MetaData) -> Vec<Option<MaintainerInfo>> {
+        meta.maintainers
+            .unwrap()
+            .iter()
+            .map(Maintainer::info)
+            .collect()
+    }
+
+    #[test]
+    fn legacy_maintainer_strings() {
+        let meta = meta(json!({
+            "maintainers": ["Eelco Dolstra <eelco.dolstra@logicblox.com>", "viric"],
+        }));
+
+        assert_eq!(
+            infos(meta),
+            [
+                Some(MaintainerInfo {
+                    name: Some("Eelco Dolstra".to_string()),
+                    email: Some("eelco.dolstra@logicblox.com".to_string()),
+                    ..Default::default()
+                }),
+                Some(MaintainerInfo {
+                    name: Some("viric".to_string()),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn maintainer_attrsets() {
+        let meta = meta(json!({
+            "maintainers": [{
+                "name": "Eelco Dolstra",
+                "github": "edolstra",
+                "githubId": 1148549,
+                "email": "edolstra+nixpkgs@gmail.com",
+            }, {
+                "github": "jane",
+                "githubId": "42",
+            }, 42, {}],
+        }));
+
+        assert_eq!(
+            infos(meta),
+            [
+                Some(MaintainerInfo {
+                    name: Some("Eelco Dolstra".to_string()),
+                    github: Some("edolstra".to_string()),
+                    github_id: Some(1148549),
+                    email: Some("edolstra+nixpkgs@gmail.com".to_string()),
+                    matrix: None,
+                }),
+                // A githubId of the wrong type doesn't lose the other fields
+                Some(MaintainerInfo {
+                    github: Some("jane".to_string()),
+                    github_id: Some(42),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn single_maintainer() {
+        let attrset = meta(json!({ "maintainers": { "github": "jane" } }));
+        assert_eq!(
+            infos(attrset),
+            [Some(MaintainerInfo {
+                github: Some("jane".to_string()),
+                ..Default::default()
+            })]
+        );
+
+        let legacy = meta(json!({ "maintainers": "Jane <jane@example.com>" }));
+        assert_eq!(
+            infos(legacy),
+            [Some(MaintainerInfo {
+                name: Some("Jane".to_string()),
+                email: Some("jane@example.com".to_string()),
+                ..Default::default()
+            })]
+        );
+    }
+
+    #[test]
+    fn unknown_shapes_are_kept() {
+        let value = json!({
+            "homepage": ["https://example.com", { "url": "https://example.org" }, null],
+            "changelog": { "url": "https://example.com/NEWS" },
+            "maintainers": [
+                "Jane <jane@example.com>",
+                42,
+                { "github": "jane", "githubId": "42" },
+                { "github": "jane", "keys": [{ "fingerprint": "AAAA" }] },
+            ],
+        });
+        let meta = meta(value.clone());
+
+        assert_eq!(
+            meta.homepage.as_ref().unwrap().iter().next(),
+            Some(&Link::Url("https://example.com".to_string()))
+        );
+        let serialized = serde_json::to_value(&meta).unwrap();
+        for key in ["homepage", "changelog", "maintainers"] {
+            assert_eq!(serialized[key], value[key]);
+        }
+    }
+
+    #[test]
+    fn spdx_license_string() {
+        let meta = meta(json!({ "license": "MIT" }));
+        let license = meta.license.unwrap();
+        assert_eq!(license, OneOrMany::One(License::Spdx("MIT".to_string())));
+        assert_eq!(
+            license.iter().next().unwrap().info(),
+            Some(LicenseInfo {
+                spdx_id: Some("MIT".to_string()),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn mixed_license_list() {
+        let meta = meta(json!({
+            "license": [
+                { "shortName": "unfree", "fullName": "Unfree", "free": false },
+                "asl20",
+                ["not", "a", "license"],
+            ],
+        }));
+
+        let licenses = meta
+            .license
+            .unwrap()
+            .iter()
+            .filter_map(License::info)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            licenses,
+            vec![
+                LicenseInfo {
+                    short_name: Some("unfree".to_string()),
+                    full_name: Some("Unfree".to_string()),
+                    free: Some(false),
+                    ..Default::default()
+                },
+                // The unrecognised entry has nothing to identify it by
+                LicenseInfo {
+                    spdx_id: Some("asl20".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn platform_patterns() {
+        let meta = meta(json!({
+            "platforms": ["x86_64-linux", { "kernel": { "name": "darwin" } }],
+            "badPlatforms": [{ "cpu": { "bits": 32 } }],
+        }));
+
+        assert_eq!(
+            meta.platforms,
+            Some(vec![
+                Platform::System("x86_64-linux".to_string()),
+                Platform::Pattern(json!({ "kernel": { "name": "darwin" } })),
+            ])
+        );
+        assert_eq!(
+            meta.bad_platforms,
+            Some(vec![Platform::Pattern(json!({ "cpu": { "bits": 32 } }))])
+        );
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let meta = meta(json!({
+            "license": "MIT",
+            "maintainers": ["Jane <jane@example.com>"],
+            "platforms": [{ "kernel": { "name": "linux" } }],
+        }));
+
+        let reparsed: MetaData =
+            serde_json::from_value(serde_json::to_value(&meta).unwrap()).unwrap();
+        assert_eq!(reparsed, meta);
+    }
+}
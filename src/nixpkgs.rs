@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use std::{path::Path, str::FromStr};
+use tokio::process::Command;
+
+/// Tarball of a nixpkgs revision on GitHub
+pub const GITHUB_TARBALL: &str = "https://github.com/NixOS/nixpkgs/archive/{rev}.tar.gz";
+
+/// Where the nixpkgs tree of a revision is evaluated from
+///
+/// `{rev}` in a tarball URL or flake reference is replaced with the revision being indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NixpkgsSource {
+    /// Tarball URL, e.g. `https://github.com/NixOS/nixpkgs/archive/{rev}.tar.gz`
+    Tarball(String),
+    /// Flake reference, e.g. `github:NixOS/nixpkgs/{rev}` or `git+https://example.com/nixpkgs?rev={rev}`
+    Flake(String),
+    /// Local checkout, evaluated as-is whatever the revision
+    Path(String),
+}
+
+impl Default for NixpkgsSource {
+    fn default() -> Self {
+        NixpkgsSource::Tarball(GITHUB_TARBALL.to_string())
+    }
+}
+
+impl FromStr for NixpkgsSource {
+    type Err = anyhow::Error;
+
+    /// URLs are tarballs, anything that looks like a filesystem path is a checkout and
+    /// everything else a flake reference
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(NixpkgsSource::Tarball(s.to_string()))
+        } else if s.starts_with('/') || s.starts_with('.') {
+            Ok(NixpkgsSource::Path(s.to_string()))
+        } else if s.contains(':') {
+            Ok(NixpkgsSource::Flake(s.to_string()))
+        } else {
+            anyhow::bail!(
+                "Expected a tarball URL, a path or a flake reference, got {}",
+                s
+            )
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FlakeMetadata {
+    path: String,
+}
+
+impl NixpkgsSource {
+    /// Whether every revision is evaluated from the same tree, as with a local checkout or a
+    /// tarball URL or flake reference without `{rev}`
+    pub fn is_fixed(&self) -> bool {
+        match self {
+            NixpkgsSource::Tarball(source) | NixpkgsSource::Flake(source) => {
+                !source.contains("{rev}")
+            }
+            NixpkgsSource::Path(_) => true,
+        }
+    }
+
+    /// Fetch the tree of `rev` if needed, and return its path to evaluate from
+    pub async fn resolve(&self, rev: &str) -> Result<String> {
+        let nixpath = match self {
+            NixpkgsSource::Tarball(url) => {
+                let url = url.replace("{rev}", rev);
+                info!("Fetching {}", url);
+                let output = Command::new("nix-instantiate")
+                    .arg("--eval")
+                    .arg("-E")
+                    .arg("with import <nixpkgs> {}; pkgs.path")
+                    .arg("-I")
+                    .arg(format!("nixpkgs={}", url))
+                    .output()
+                    .await
+                    .context("Failed to execute nix-instantiate")?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Failed to fetch {}: {}",
+                        url,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                String::from_utf8_lossy(&output.stdout).trim().to_string()
+            }
+            NixpkgsSource::Flake(flake) => {
+                let flake = flake.replace("{rev}", rev);
+                info!("Fetching {}", flake);
+                let output = Command::new("nix")
+                    .args(["--extra-experimental-features", "nix-command flakes"])
+                    .args(["flake", "metadata", "--json"])
+                    .arg(&flake)
+                    .output()
+                    .await
+                    .context("Failed to execute nix")?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Failed to fetch {}: {}",
+                        flake,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                let metadata: FlakeMetadata = serde_json::from_slice(&output.stdout)
+                    .context("Failed to parse flake metadata")?;
+                metadata.path
+            }
+            NixpkgsSource::Path(path) => Path::new(path)
+                .canonicalize()
+                .with_context(|| format!("Failed to find nixpkgs checkout {}", path))?
+                .to_string_lossy()
+                .to_string(),
+        };
+
+        debug!("nixpath: {}", nixpath);
+        Ok(nixpath)
+    }
+}
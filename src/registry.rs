@@ -0,0 +1,70 @@
+// Output of registry.nix's genRegistry, shared by nixpkgs and flake evaluation
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{ddb::Store, MetaData, Pkg};
+
+/// A package as recorded by genRegistry, `pname` and `meta` are only present with `withMeta`
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegistryEntry {
+    #[serde(rename = "storePaths")]
+    pub outputs: HashMap<String, String>,
+    pub version: Option<String>,
+    pub pname: Option<String>,
+    pub meta: Option<MetaData>,
+}
+
+/// Attribute path to package
+pub type Registry = HashMap<String, RegistryEntry>;
+
+pub fn parse(data: &[u8]) -> Result<Registry> {
+    serde_json::from_slice(data).context("Failed to parse registry")
+}
+
+/// Add every output of `registry`, evaluated for `system`, to the store path index
+///
+/// Store paths shared between attributes, or with an earlier system, keep their first entry
+/// and gain the extra attributes.
+pub fn add_stores(store: &mut HashMap<String, Store>, system: &str, registry: &Registry) {
+    for (attr, pkg) in registry {
+        for (name, outpath) in &pkg.outputs {
+            if let Some(store_val) = store.get_mut(outpath) {
+                if !store_val.attribute.contains(attr) {
+                    store_val.attribute.push(attr.to_string());
+                }
+            } else {
+                store.insert(
+                    outpath.to_string(),
+                    Store {
+                        attribute: vec![attr.to_string()],
+                        version: pkg.version.clone(),
+                        system: system.to_string(),
+                        output: name.to_string(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Packages of a registry evaluated with `withMeta`, as in packages.json
+pub fn packages(registry: Registry) -> HashMap<String, Pkg> {
+    registry
+        .into_iter()
+        .map(|(attr, pkg)| {
+            let pname = pkg
+                .pname
+                .unwrap_or_else(|| attr.rsplit('.').next().unwrap_or(&attr).to_string());
+            let pkg = Pkg {
+                attribute: attr.clone(),
+                outputs: pkg.outputs,
+                meta: pkg.meta.unwrap_or_default(),
+                pname,
+                version: pkg.version.unwrap_or_default(),
+            };
+            (attr, pkg)
+        })
+        .collect()
+}
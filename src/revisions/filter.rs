@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use glob::Pattern;
+
+use super::source::Release;
+
+/// Which channels and releases to process
+///
+/// The default selects every channel and every release past its marker.
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+impl ReleaseFilter {
+    /// `include` and `exclude` are channel globs such as `nixos/*`, an empty `include` selects
+    /// every channel. `since` and `until` are inclusive, either RFC 3339 timestamps or dates.
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        since: Option<&str>,
+        until: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Self> {
+        let patterns = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|x| Pattern::new(x).with_context(|| format!("Invalid channel glob {}", x)))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(ReleaseFilter {
+            include: patterns(include)?,
+            exclude: patterns(exclude)?,
+            since: since.map(|x| parse_date(x, false)).transpose()?,
+            // A bare date covers the whole day
+            until: until.map(|x| parse_date(x, true)).transpose()?,
+            limit,
+        })
+    }
+
+    pub fn channel(&self, channel: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|x| x.matches(channel)))
+            && !self.exclude.iter().any(|x| x.matches(channel))
+    }
+
+    /// Whether releases are selected by date instead of by marker
+    ///
+    /// Such runs are backfills, so they should leave markers where they are.
+    pub fn is_window(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    /// Narrow `releases`, oldest first, to the date window and per-channel limit
+    pub fn apply(&self, mut releases: Vec<Release>) -> Result<Vec<Release>> {
+        if self.is_window() {
+            let mut selected = vec![];
+            for release in releases {
+                let date = DateTime::parse_from_rfc3339(&release.last_modified)
+                    .with_context(|| format!("Invalid date for {}", release.name()))?;
+                let after = self.since.map_or(true, |x| date >= x);
+                let before = self.until.map_or(true, |x| date <= x);
+                if after && before {
+                    selected.push(release);
+                }
+            }
+            releases = selected;
+        }
+
+        if let Some(limit) = self.limit {
+            releases.truncate(limit);
+        }
+
+        Ok(releases)
+    }
+}
+
+/// Parse an RFC 3339 timestamp or a `YYYY-MM-DD` date, as the start or end of that day
+fn parse_date(date: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid date {}, expected YYYY-MM-DD", date))?;
+    let time = if end_of_day {
+        NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).context("Invalid time")?
+    } else {
+        NaiveTime::MIN
+    };
+    Ok(day.and_time(time).and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn releases(days: &[u32]) -> Vec<Release> {
+        days.iter()
+            .map(|day| Release {
+                key: format!("nixos/unstable/nixos-24.05pre{}", day),
+                last_modified: format!("2024-01-{:02}T12:00:00.000Z", day),
+            })
+            .collect()
+    }
+
+    fn names(releases: &[Release]) -> Vec<&str> {
+        releases.iter().map(Release::name).collect()
+    }
+
+    #[test]
+    fn bare_dates_cover_the_day() {
+        assert_eq!(
+            parse_date("2024-01-02", false).unwrap().to_rfc3339(),
+            "2024-01-02T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_date("2024-01-02", true).unwrap(),
+            "2024-01-02T23:59:59.999999999Z"
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        );
+        assert!(parse_date("02/01/2024", false).is_err());
+    }
+
+    #[test]
+    fn timestamps_keep_their_offset() {
+        // A timestamp is exact, whether it starts or ends the window
+        for end_of_day in [false, true] {
+            assert_eq!(
+                parse_date("2024-01-02T01:00:00+02:00", end_of_day)
+                    .unwrap()
+                    .to_rfc3339(),
+                "2024-01-01T23:00:00+00:00"
+            );
+        }
+    }
+
+    #[test]
+    fn channel_globs() {
+        let all = ReleaseFilter::new(&[], &[], None, None, None).unwrap();
+        assert!(all.channel("nixos/unstable"));
+
+        let filter = ReleaseFilter::new(
+            &["nixos/*".to_string(), "nixpkgs/unstable".to_string()],
+            &["nixos/*-small".to_string()],
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(filter.channel("nixos/unstable"));
+        assert!(filter.channel("nixpkgs/unstable"));
+        assert!(!filter.channel("nixos/unstable-small"));
+        assert!(!filter.channel("nixpkgs/23.11"));
+
+        assert!(ReleaseFilter::new(&["nixos/[".to_string()], &[], None, None, None).is_err());
+    }
+
+    #[test]
+    fn window_and_limit() {
+        let filter =
+            ReleaseFilter::new(&[], &[], Some("2024-01-02"), Some("2024-01-04"), Some(2)).unwrap();
+        assert!(filter.is_window());
+        let selected = filter.apply(releases(&[1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(names(&selected), ["nixos-24.05pre2", "nixos-24.05pre3"]);
+
+        // The bare until date includes releases later that day
+        let filter = ReleaseFilter::new(&[], &[], None, Some("2024-01-02"), None).unwrap();
+        let selected = filter.apply(releases(&[1, 2, 3])).unwrap();
+        assert_eq!(names(&selected), ["nixos-24.05pre1", "nixos-24.05pre2"]);
+
+        let filter = ReleaseFilter::new(&[], &[], None, None, Some(1)).unwrap();
+        assert!(!filter.is_window());
+        let selected = filter.apply(releases(&[1, 2, 3])).unwrap();
+        assert_eq!(names(&selected), ["nixos-24.05pre1"]);
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs, io::Write};
+
+pub mod filter;
+pub mod source;
+
+use filter::ReleaseFilter;
+use source::{Release, RevisionSource};
+
+/// Marker shared by the s3 and ddb commands, which also lists a channel as one to process
+pub const LAST: &str = "last";
+/// Marker of the history command
+pub const HISTORY_LAST: &str = "history-last";
+/// Marker of the feed command
+pub const FEED_LAST: &str = "feed-last";
+
+/// Releases of every channel in `dir` past its `marker`
+pub async fn get_revisions(
+    dir: &str,
+    source: &impl RevisionSource,
+    filter: &ReleaseFilter,
+    marker: &str,
+) -> Result<HashMap<String, Vec<Release>>> {
+    let mut out = HashMap::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() && path.ends_with("nixos") {
+            let channel = path
+                .file_name()
+                .context("Failed to read file name")?
+                .to_str()
+                .context("Failed to get channel name")?
+                .to_string();
+
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.is_dir() {
+                    let release = path
+                        .file_name()
+                        .context("Failed to read file name")?
+                        .to_str()
+                        .context("Failed to get release name")?
+                        .to_string();
+                    let channel = format!("{}/{}", channel, release);
+                    if !filter.channel(&channel) {
+                        continue;
+                    }
+
+                    let last = read_marker(dir, &channel, marker)?;
+
+                    let revs = get_all_objects(source, &channel, &last, filter).await?;
+
+                    out.insert(channel, revs);
+                }
+            }
+        } else if path.is_dir() && path.ends_with("nixpkgs") {
+            let channel = path
+                .file_name()
+                .context("Failed to read file name")?
+                .to_str()
+                .context("Failed to get release name")?
+                .to_string();
+            if !filter.channel(&channel) {
+                continue;
+            }
+
+            let last = read_marker(dir, &channel, marker)?;
+
+            let revs = get_all_objects(source, &channel, &last, filter).await?;
+
+            out.insert(channel, revs);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Look up a single release, given as `{channel}/{release name}`, regardless of markers
+pub async fn get_release(
+    source: &impl RevisionSource,
+    release: &str,
+) -> Result<HashMap<String, Vec<Release>>> {
+    let (channel, name) = release
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .with_context(|| format!("Expected {{channel}}/{{release}}, got {}", release))?;
+
+    let release = source
+        .list(channel)
+        .await?
+        .into_iter()
+        .find(|x| x.name() == name)
+        .with_context(|| format!("No release {} in {}", name, channel))?;
+
+    Ok(HashMap::from([(channel.to_string(), vec![release])]))
+}
+
+async fn get_all_objects(
+    source: &impl RevisionSource,
+    channel: &str,
+    last_key: &str,
+    filter: &ReleaseFilter,
+) -> Result<Vec<Release>> {
+    let mut objects = source.list(channel).await?;
+
+    objects.sort_by(|a, b| a.last_modified.cmp(&b.last_modified));
+
+    // Remove everything before last, inclusive, unless a date window picks releases instead
+    if !filter.is_window() {
+        if let Some(last) = objects.iter().position(|x| x.name() == last_key) {
+            objects.drain(0..last + 1);
+        }
+    }
+
+    filter.apply(objects)
+}
+
+/// Record `rev` as the last revision of `channel` processed by the command owning `marker`,
+/// so that reruns resume after it
+pub fn update_marker(dir: &str, channel: &str, marker: &str, rev: &str) -> Result<()> {
+    write_atomic(&format!("{}/{}/{}", dir, channel, marker), rev.as_bytes())
+}
+
+/// Last revision of `channel` processed by the command owning `marker`
+///
+/// Markers other than [`LAST`] are created on first use, so a missing one is empty and every
+/// release of the channel is pending.
+pub fn read_marker(dir: &str, channel: &str, marker: &str) -> Result<String> {
+    let path = format!("{}/{}/{}", dir, channel, marker);
+    match fs::read_to_string(&path) {
+        Ok(last) => Ok(last.trim().to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && marker != LAST => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path)),
+    }
+}
+
+/// Replace `path` with `contents` such that an interruption leaves either the old or the new file
+pub fn write_atomic(path: &str, contents: &[u8]) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut file = fs::File::create(&tmp).with_context(|| format!("Failed to create {}", tmp))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path))?;
+    Ok(())
+}
+
+pub fn add_failed_revision(dir: &str, rev: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}/failed", dir))?;
+    file.write_all(format!("{}\n", rev).as_bytes())?;
+    Ok(())
+}
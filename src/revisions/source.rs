@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use crate::fixtures::Fixtures;
+
+pub const NIX_RELEASES: &str = "https://nix-releases.s3.amazonaws.com/";
+
+/// A single channel release, e.g. `nixos/unstable/nixos-24.05pre564493.b0d36bd0a420`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub key: String,
+    pub last_modified: String,
+}
+
+impl Release {
+    /// Release name without the channel prefix
+    pub fn name(&self) -> &str {
+        self.key
+            .trim_matches('"')
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+/// Somewhere the list of releases for a channel can be discovered
+pub trait RevisionSource {
+    /// List every release directly under `prefix` (e.g. `nixos/unstable`), in any order
+    fn list(&self, prefix: &str) -> impl Future<Output = Result<Vec<Release>>> + Send;
+}
+
+#[derive(Debug, Deserialize)]
+struct ListBucketResult {
+    #[serde(rename = "Contents", default)]
+    contents: Vec<Content>,
+    #[serde(rename = "IsTruncated")]
+    is_truncated: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Content {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "LastModified")]
+    pub last_modified: String,
+}
+
+/// Public S3 ListBucket listing, such as the `nix-releases` bucket
+#[derive(Debug, Clone)]
+pub struct S3Listing {
+    url: String,
+    fixtures: Option<Fixtures>,
+}
+
+impl S3Listing {
+    pub fn new(url: &str) -> Self {
+        S3Listing {
+            url: url.to_string(),
+            fixtures: None,
+        }
+    }
+
+    /// Read recorded ListBucket pages from fixtures instead of the network
+    pub fn offline(fixtures: Fixtures) -> Self {
+        S3Listing {
+            url: NIX_RELEASES.to_string(),
+            fixtures: Some(fixtures),
+        }
+    }
+}
+
+impl Default for S3Listing {
+    fn default() -> Self {
+        S3Listing::new(NIX_RELEASES)
+    }
+}
+
+impl RevisionSource for S3Listing {
+    async fn list(&self, prefix: &str) -> Result<Vec<Release>> {
+        let mut truncated = true;
+        let mut marker = "".to_string();
+        let url = format!("{}?delimiter=/&prefix={}/", self.url, prefix);
+
+        let mut objects = vec![];
+        let mut page = 0;
+
+        while truncated {
+            let output = match &self.fixtures {
+                Some(fixtures) => fixtures.listing_page(prefix, page)?,
+                None => {
+                    reqwest::get(format!("{}&marker={}", url, marker))
+                        .await?
+                        .text()
+                        .await?
+                }
+            };
+            page += 1;
+
+            let output: ListBucketResult = quick_xml::de::from_str(&output)?;
+
+            for content in &output.contents {
+                objects.push(Release {
+                    key: content.key.clone(),
+                    last_modified: content.last_modified.clone(),
+                });
+            }
+
+            truncated = output.is_truncated;
+            marker = output
+                .contents
+                .last()
+                .context("Failed to get last item")?
+                .key
+                .clone();
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Local directory mirror of releases, laid out as `{root}/{channel}/{release}/`
+///
+/// The release date is read from a `last-modified` file in each release directory, holding an
+/// RFC 3339 timestamp such as the release's `LastModified` on S3. Releases without one fall back
+/// to the modification time of their `git-revision`, which is written once when mirrored.
+#[derive(Debug, Clone)]
+pub struct LocalMirror {
+    root: PathBuf,
+}
+
+impl LocalMirror {
+    pub fn new(root: &str) -> Self {
+        LocalMirror {
+            root: PathBuf::from(root),
+        }
+    }
+}
+
+/// Release date of the mirrored release in `dir`
+fn mirrored_date(dir: &Path) -> Result<DateTime<Utc>> {
+    let path = dir.join("last-modified");
+    match std::fs::read_to_string(&path) {
+        Ok(timestamp) => Ok(DateTime::parse_from_rfc3339(timestamp.trim())
+            .with_context(|| format!("Invalid timestamp in {}", path.display()))?
+            .into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let path = dir.join("git-revision");
+            let metadata = std::fs::metadata(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Ok(metadata.modified()?.into())
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+impl RevisionSource for LocalMirror {
+    async fn list(&self, prefix: &str) -> Result<Vec<Release>> {
+        let dir = self.root.join(prefix);
+        let mut objects = vec![];
+
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read mirror directory {}", dir.display()))?
+        {
+            let entry = entry?;
+            // e.g. checksums or notes kept next to the releases
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let release = entry
+                .file_name()
+                .to_str()
+                .context("Failed to get release name")?
+                .to_string();
+            let modified = mirrored_date(&entry.path())?;
+
+            objects.push(Release {
+                key: format!("{}/{}", prefix, release),
+                last_modified: modified.to_rfc3339_opts(SecondsFormat::Millis, true),
+            });
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Static JSON manifest mapping each channel prefix to its releases
+///
+/// ```json
+/// { "nixos/unstable": [{ "key": "nixos/unstable/nixos-24.05pre564493.b0d36bd0a420", "last_modified": "2024-03-02T01:23:45.000Z" }] }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    path: PathBuf,
+}
+
+impl Manifest {
+    pub fn new(path: &str) -> Self {
+        Manifest {
+            path: PathBuf::from(path),
+        }
+    }
+}
+
+impl RevisionSource for Manifest {
+    async fn list(&self, prefix: &str) -> Result<Vec<Release>> {
+        let manifest = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Failed to read manifest {}", self.path.display()))?;
+        let mut manifest: HashMap<String, Vec<Release>> = serde_json::from_str(&manifest)?;
+
+        Ok(manifest.remove(prefix).unwrap_or_default())
+    }
+}
@@ -1,33 +1,209 @@
-use std::{path::Path, process::Stdio};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
 
-use aws_sdk_s3::{primitives::ByteStream, Client};
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
 use log::info;
-use rusqlite::Connection;
-use tokio::{io::AsyncWriteExt, process::Command};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
-use crate::s3::nix::{getmeta, getrevision};
+use crate::{
+    fixtures::Fixtures,
+    meta::{License, LicenseInfo, Maintainer, MaintainerInfo},
+    revisions::source::Release,
+    s3::{
+        index::{update_index, IndexEntry},
+        nix::{getmeta, getrevision},
+        upload::UploadTarget,
+    },
+    Pkg,
+};
+
+/// Ranked full-text search over the generated database
+///
+/// `?1` is an FTS5 query (e.g. `firefox` or `"web browser"`) and `?2` the maximum number of
+/// results. Matches are ordered by bm25, weighting attribute and pname hits above main program,
+/// description and long description hits, in that order.
+pub const SEARCH_QUERY: &str = r#"
+    SELECT "pkgs"."attribute", "pkgs"."pname", "pkgs"."version", "meta"."description"
+    FROM "pkgs_fts"
+    JOIN "pkgs" ON "pkgs"."attribute" = "pkgs_fts"."attribute"
+    JOIN "meta" ON "meta"."attribute" = "pkgs_fts"."attribute"
+    WHERE "pkgs_fts" MATCH ?1
+    ORDER BY bm25("pkgs_fts", 10.0, 10.0, 2.0, 1.0, 5.0)
+    LIMIT ?2
+"#;
+
+/// Attributes providing a store path
+///
+/// `?1` is a store path without the `/nix/store/` prefix, as used as the key of the store path
+/// index. Returns the attribute, output name and version of every package with that output.
+pub const STORE_PATH_QUERY: &str = r#"
+    SELECT "outputs"."attribute", "outputs"."output", "pkgs"."version"
+    FROM "outputs"
+    JOIN "pkgs" ON "pkgs"."attribute" = "outputs"."attribute"
+    WHERE "outputs"."store_path" = ?1
+"#;
+
+/// Brotli settings used to compress databases before upload
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    /// Quality between 0 and 11
+    pub quality: u32,
+    /// Base 2 logarithm of the sliding window size, between 10 and 24
+    pub window: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            quality: 11,
+            window: 24,
+        }
+    }
+}
 
 pub async fn create_db(
-    client: &Client,
+    target: Option<&impl UploadTarget>,
     channel: &str,
-    revision: &str,
-    upload: bool,
-    bucket: &str,
+    release: &Release,
+    fixtures: Option<&Fixtures>,
+    compression: Compression,
 ) -> Result<()> {
-    let data = getmeta(channel, revision).await?;
-    let rev = getrevision(channel, revision).await?;
+    let revision = release.name();
+    let data = getmeta(channel, revision, fixtures).await?;
+    let rev = getrevision(channel, revision, fixtures).await?;
 
     info!("Got data, creating db");
 
+    publish_db(
+        target,
+        channel,
+        revision,
+        &rev,
+        Some(&release.last_modified),
+        data,
+        compression,
+    )
+    .await
+}
+
+/// Write `data` to `{rev}.db`, or publish it to `target` as `release` of `channel`
+///
+/// `timestamp` orders the release against others of the channel in the index. Builds without
+/// one, such as of an arbitrary revision, are listed with their build time and never become the
+/// channel's `latest`.
+pub async fn publish_db(
+    target: Option<&impl UploadTarget>,
+    channel: &str,
+    release: &str,
+    rev: &str,
+    timestamp: Option<&str>,
+    data: HashMap<String, Pkg>,
+    compression: Compression,
+) -> Result<()> {
     // Create database with rusqlite
     let db = format!("{}.db", rev);
+    // Channels at the same revision may be processed concurrently, so each builds its own copy
+    let building = format!("{}.{}.tmp", db, channel.replace('/', "-"));
 
     // Delete old db
-    let _ = std::fs::remove_file(&db);
-    let _ = std::fs::remove_file(format!("{}.br", db));
+    let _ = std::fs::remove_file(&building);
+    let _ = std::fs::remove_file(format!("{}.br", building));
+
+    {
+        let building = building.clone();
+        tokio::task::spawn_blocking(move || write_db(&building, data)).await??;
+    }
+
+    if let Some(target) = target {
+        let (size, sha256) = {
+            let building = building.clone();
+            tokio::task::spawn_blocking(move || digest(&building)).await??
+        };
+
+        // Compress with brotli
+        info!("Compressing with brotli");
+        let compressed = format!("{}.br", building);
+        {
+            let building = building.clone();
+            let compressed = compressed.clone();
+            tokio::task::spawn_blocking(move || compress(&building, &compressed, compression))
+                .await??;
+        }
+        let _ = std::fs::remove_file(&building);
+
+        // Upload
+        target.put(rev, Path::new(&compressed), "br").await?;
+        update_index(
+            target,
+            channel,
+            IndexEntry {
+                release: release.to_string(),
+                rev: rev.to_string(),
+                timestamp: timestamp
+                    .map(str::to_string)
+                    .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+                size,
+                sha256,
+                adhoc: timestamp.is_none(),
+            },
+        )
+        .await?;
+
+        // Cleanup
+        let _ = std::fs::remove_file(&compressed);
+    } else {
+        std::fs::rename(&building, &db)
+            .with_context(|| format!("Failed to move database to {}", db))?;
+    }
+
+    Ok(())
+}
+
+/// Size and hex SHA-256 of the file at `path`
+fn digest(path: &str) -> Result<(u64, String)> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    Ok((size, sha256))
+}
 
-    let conn = Connection::open(&db).expect("Failed to open database");
+fn compress(input: &str, output: &str, compression: Compression) -> Result<()> {
+    let mut reader =
+        BufReader::new(File::open(input).with_context(|| format!("Failed to open {}", input))?);
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("Failed to create {}", output))?,
+    );
+
+    let params = brotli::enc::BrotliEncoderParams {
+        quality: compression.quality as i32,
+        lgwin: compression.window as i32,
+        ..Default::default()
+    };
+    brotli::BrotliCompress(&mut reader, &mut writer, &params)
+        .with_context(|| format!("Failed to compress {}", input))?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()
+        .with_context(|| format!("Failed to write {}", output))?;
+
+    Ok(())
+}
+
+fn write_db(db: &str, data: HashMap<String, Pkg>) -> Result<()> {
+    let mut conn = Connection::open(db).context("Failed to open database")?;
 
     // Create table
     conn.execute(
@@ -39,7 +215,7 @@ pub async fn create_db(
             )"#,
         [],
     )
-    .expect("Failed to create table");
+    .context("Failed to create table")?;
 
     conn.execute(
         r#"
@@ -65,137 +241,212 @@ pub async fn create_db(
             "#,
         [],
     )
-    .expect("Failed to create table");
+    .context("Failed to create table")?;
 
     // Create index
     conn.execute(r#"CREATE INDEX "idx_pkgs" ON "pkgs" ("attribute")"#, [])
-        .expect("Failed to create index");
+        .context("Failed to create index")?;
 
     conn.execute(r#"CREATE INDEX "idx_meta" ON "meta" ("attribute")"#, [])
-        .expect("Failed to create index");
-
-    let mut wtr = csv::Writer::from_writer(vec![]);
-    for (_path, store) in &data {
-        wtr.serialize((
-            store.attribute.to_string(),
-            store.pname.to_string(),
-            store.version.to_string(),
-        ))?;
-    }
-    let pkgdata = String::from_utf8(wtr.into_inner()?)?;
-    let mut pkgcmd = Command::new("sqlite3")
-        .arg("-csv")
-        .arg(&db)
-        .arg(".import '|cat -' pkgs")
-        .stdin(Stdio::piped())
-        .spawn()?;
-    let pkgcmd_stdin = pkgcmd.stdin.as_mut().unwrap();
-    pkgcmd_stdin.write_all(pkgdata.as_bytes()).await?;
-    let _status = pkgcmd.wait().await?;
-
-    let mut metawtr = csv::Writer::from_writer(vec![]);
+        .context("Failed to create index")?;
+
+    // Create license and maintainer tables
+    conn.execute_batch(
+        r#"
+        CREATE TABLE "licenses" (
+            "id"	INTEGER NOT NULL,
+            "spdx_id"	TEXT,
+            "short_name"	TEXT,
+            "full_name"	TEXT,
+            "url"	TEXT,
+            "free"	INTEGER,
+            "redistributable"	INTEGER,
+            PRIMARY KEY("id")
+        );
+        CREATE TABLE "pkg_licenses" (
+            "attribute"	TEXT NOT NULL,
+            "license"	INTEGER NOT NULL,
+            FOREIGN KEY("attribute") REFERENCES "pkgs" ("attribute"),
+            FOREIGN KEY("license") REFERENCES "licenses" ("id"),
+            PRIMARY KEY("attribute", "license")
+        );
+        CREATE TABLE "maintainers" (
+            "id"	INTEGER NOT NULL,
+            "name"	TEXT,
+            "github"	TEXT,
+            "github_id"	INTEGER,
+            "email"	TEXT,
+            "matrix"	TEXT,
+            PRIMARY KEY("id")
+        );
+        CREATE TABLE "pkg_maintainers" (
+            "attribute"	TEXT NOT NULL,
+            "maintainer"	INTEGER NOT NULL,
+            FOREIGN KEY("attribute") REFERENCES "pkgs" ("attribute"),
+            FOREIGN KEY("maintainer") REFERENCES "maintainers" ("id"),
+            PRIMARY KEY("attribute", "maintainer")
+        );
+        CREATE TABLE "outputs" (
+            "attribute"	TEXT NOT NULL,
+            "output"	TEXT NOT NULL,
+            "store_path"	TEXT NOT NULL,
+            FOREIGN KEY("attribute") REFERENCES "pkgs" ("attribute"),
+            PRIMARY KEY("attribute", "output")
+        );
+        CREATE INDEX "idx_licenses_spdx_id" ON "licenses" ("spdx_id");
+        CREATE INDEX "idx_licenses_free" ON "licenses" ("free");
+        CREATE INDEX "idx_pkg_licenses" ON "pkg_licenses" ("license");
+        CREATE INDEX "idx_maintainers_github" ON "maintainers" ("github");
+        CREATE INDEX "idx_maintainers_email" ON "maintainers" ("email");
+        CREATE INDEX "idx_pkg_maintainers" ON "pkg_maintainers" ("maintainer");
+        CREATE INDEX "idx_outputs_store_path" ON "outputs" ("store_path");
+        "#,
+    )
+    .context("Failed to create license, maintainer and output tables")?;
+
+    // Create full-text search table, see SEARCH_QUERY
+    conn.execute(
+        r#"
+        CREATE VIRTUAL TABLE "pkgs_fts" USING fts5(
+            "attribute",
+            "pname",
+            "description",
+            "long_description",
+            "main_program",
+            tokenize = 'porter unicode61'
+        )
+            "#,
+        [],
+    )
+    .context("Failed to create full-text search table")?;
 
     // Insert data
-    for (_path, store) in data {
-        // Insert into meta table
-        metawtr
-            .serialize((
-                store.attribute,
-                store.meta.description.unwrap_or_default(),
-                store.meta.long_description.unwrap_or_default(),
-                store.meta.branch.unwrap_or_default(),
-                store
-                    .meta
-                    .homepage
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .download_page
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .changelog
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .license
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .maintainers
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store.meta.main_program.unwrap_or_default(),
-                store
-                    .meta
-                    .platforms
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .bad_platforms
-                    .map(|x| x.to_string())
-                    .unwrap_or_default(),
-                store
-                    .meta
-                    .broken
-                    .map(|x| if x { 1 } else { 0 })
-                    .unwrap_or(0)
-                    .to_string(),
-                store
-                    .meta
-                    .unfree
-                    .map(|x| if x { 1 } else { 0 })
-                    .unwrap_or(0)
-                    .to_string(),
-                store
-                    .meta
-                    .insecure
-                    .map(|x| if x { 1 } else { 0 })
-                    .unwrap_or(0)
-                    .to_string(),
-            ))
-            .expect("Failed to insert into meta table");
-    }
+    let tx = conn.transaction()?;
+    {
+        let mut pkgstmt = tx.prepare(
+            r#"INSERT INTO "pkgs" ("attribute", "pname", "version") VALUES (?1, ?2, ?3)"#,
+        )?;
+        let mut metastmt = tx.prepare(
+            r#"INSERT INTO "meta" VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"#,
+        )?;
+        let mut ftsstmt = tx.prepare(r#"INSERT INTO "pkgs_fts" VALUES (?1, ?2, ?3, ?4, ?5)"#)?;
+        let mut licensestmt = tx.prepare(
+            r#"INSERT INTO "licenses" ("spdx_id", "short_name", "full_name", "url", "free", "redistributable") VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+        )?;
+        let mut pkglicensestmt = tx.prepare(
+            r#"INSERT OR IGNORE INTO "pkg_licenses" ("attribute", "license") VALUES (?1, ?2)"#,
+        )?;
+        let mut maintainerstmt = tx.prepare(
+            r#"INSERT INTO "maintainers" ("name", "github", "github_id", "email", "matrix") VALUES (?1, ?2, ?3, ?4, ?5)"#,
+        )?;
+        let mut pkgmaintainerstmt = tx.prepare(
+            r#"INSERT OR IGNORE INTO "pkg_maintainers" ("attribute", "maintainer") VALUES (?1, ?2)"#,
+        )?;
+        let mut outputstmt = tx.prepare(
+            r#"INSERT INTO "outputs" ("attribute", "output", "store_path") VALUES (?1, ?2, ?3)"#,
+        )?;
 
-    let metadata = String::from_utf8(metawtr.into_inner()?)?;
+        let mut licenses: HashMap<LicenseInfo, i64> = HashMap::new();
+        let mut maintainers: HashMap<MaintainerInfo, i64> = HashMap::new();
 
-    let mut metacmd = Command::new("sqlite3")
-        .arg("-csv")
-        .arg(&db)
-        .arg(".import '|cat -' meta")
-        .stdin(Stdio::piped())
-        .spawn()?;
-    let metacmd_stdin = metacmd.stdin.as_mut().unwrap();
-    metacmd_stdin.write_all(metadata.as_bytes()).await?;
-    let _status = metacmd.wait().await?;
+        for store in data.into_values() {
+            pkgstmt
+                .execute(params![store.attribute, store.pname, store.version])
+                .with_context(|| format!("Failed to insert {} into pkgs table", store.attribute))?;
 
-    if upload {
-        // Compress with brotli
-        info!("Compressing with brotli");
-        let mut cmd = Command::new("brotli").arg("--rm").arg(&db).spawn()?;
-        let _status = cmd.wait().await?;
-
-        // Upload to S3
-        info!("Uploading to S3");
-        let body = ByteStream::from_path(Path::new(&format!("{}.br", db))).await?;
-        client
-            .put_object()
-            .content_encoding("br")
-            .bucket(bucket)
-            .key(rev)
-            .body(body)
-            .send()
-            .await?;
+            for (output, store_path) in &store.outputs {
+                outputstmt
+                    .execute(params![store.attribute, output, store_path])
+                    .with_context(|| {
+                        format!("Failed to insert {} into outputs table", store.attribute)
+                    })?;
+            }
 
-        // Cleanup
-        let _ = std::fs::remove_file(&db);
-        let _ = std::fs::remove_file(format!("{}.br", db));
+            let meta = store.meta;
+            ftsstmt
+                .execute(params![
+                    store.attribute,
+                    store.pname,
+                    meta.description,
+                    meta.long_description,
+                    meta.main_program,
+                ])
+                .with_context(|| {
+                    format!("Failed to insert {} into search table", store.attribute)
+                })?;
+
+            // Entries without anything to identify them by would all share one empty row
+            for license in meta
+                .license
+                .iter()
+                .flat_map(|x| x.iter().filter_map(License::info))
+            {
+                let id = match licenses.get(&license) {
+                    Some(id) => *id,
+                    None => {
+                        let id = licensestmt.insert(params![
+                            license.spdx_id,
+                            license.short_name,
+                            license.full_name,
+                            license.url,
+                            license.free,
+                            license.redistributable,
+                        ])?;
+                        licenses.insert(license, id);
+                        id
+                    }
+                };
+                pkglicensestmt.execute(params![store.attribute, id])?;
+            }
+
+            for maintainer in meta
+                .maintainers
+                .iter()
+                .flat_map(|x| x.iter().filter_map(Maintainer::info))
+            {
+                let id = match maintainers.get(&maintainer) {
+                    Some(id) => *id,
+                    None => {
+                        let id = maintainerstmt.insert(params![
+                            maintainer.name,
+                            maintainer.github,
+                            maintainer.github_id,
+                            maintainer.email,
+                            maintainer.matrix,
+                        ])?;
+                        maintainers.insert(maintainer, id);
+                        id
+                    }
+                };
+                pkgmaintainerstmt.execute(params![store.attribute, id])?;
+            }
+
+            metastmt
+                .execute(params![
+                    store.attribute,
+                    meta.description,
+                    meta.long_description,
+                    meta.branch,
+                    json(&meta.homepage)?,
+                    json(&meta.download_page)?,
+                    json(&meta.changelog)?,
+                    json(&meta.license)?,
+                    json(&meta.maintainers)?,
+                    meta.main_program,
+                    json(&meta.platforms)?,
+                    json(&meta.bad_platforms)?,
+                    meta.broken,
+                    meta.unfree,
+                    meta.insecure,
+                ])
+                .with_context(|| format!("Failed to insert {} into meta table", store.attribute))?;
+        }
     }
+    tx.commit().context("Failed to commit database")?;
 
     Ok(())
 }
+
+fn json<T: Serialize>(value: &Option<T>) -> Result<Option<String>> {
+    Ok(value.as_ref().map(serde_json::to_string).transpose()?)
+}
@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+use crate::s3::upload::UploadTarget;
+
+/// Key of the index of every published database
+pub const INDEX_KEY: &str = "index.json";
+
+/// A published database, as listed in `index.json`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Channel release name, e.g. `nixos-24.05pre564493.b0d36bd0a420`
+    pub release: String,
+    /// Full git revision, also the key the database is published under
+    pub rev: String,
+    /// Release date, as reported by the release source
+    pub timestamp: String,
+    /// Size of the uncompressed database in bytes
+    pub size: u64,
+    /// Hex SHA-256 of the uncompressed database
+    pub sha256: String,
+    /// Built from an arbitrary revision rather than a release, so dated by its build time and
+    /// never the channel's `latest`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub adhoc: bool,
+}
+
+/// Serializes index updates from channels processed concurrently
+static INDEX_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Channel to its published databases, oldest first
+pub type Index = BTreeMap<String, Vec<IndexEntry>>;
+
+/// Key of the pointer to the newest database of `channel`
+pub fn latest_key(channel: &str) -> String {
+    format!("{}/latest", channel)
+}
+
+/// Add `entry` to the index and move the channel's `latest` pointer if it is the newest release
+///
+/// Ad hoc entries are listed but leave `latest` alone, and a channel with only those has none.
+pub async fn update_index(
+    target: &impl UploadTarget,
+    channel: &str,
+    entry: IndexEntry,
+) -> Result<()> {
+    let _guard = INDEX_LOCK.lock().await;
+
+    let mut index: Index = match target.get(INDEX_KEY).await? {
+        Some(index) => serde_json::from_slice(&index).context("Failed to parse index")?,
+        None => Index::new(),
+    };
+
+    let entries = index.entry(channel.to_string()).or_default();
+    entries.retain(|x| x.release != entry.release);
+    entries.push(entry);
+    entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let latest = entries.iter().rev().find(|x| !x.adhoc).cloned();
+
+    target
+        .put_bytes(
+            INDEX_KEY,
+            serde_json::to_vec_pretty(&index)?,
+            "application/json",
+        )
+        .await?;
+    if let Some(latest) = latest {
+        target
+            .put_bytes(
+                &latest_key(channel),
+                serde_json::to_vec_pretty(&latest)?,
+                "application/json",
+            )
+            .await?;
+    }
+
+    Ok(())
+}
@@ -1,2 +1,4 @@
-mod nix;
+pub mod nix;
 pub mod db;
+pub mod index;
+pub mod upload;
@@ -1,13 +1,16 @@
 // https://releases.nixos.org/nixos/unstable/nixos-24.05pre564493.b0d36bd0a420/packages.json.br
 
 use std::collections::HashMap;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::Deserialize;
-use crate::{MetaData, Pkg};
+use crate::{fixtures::Fixtures, MetaData, Pkg};
 
 #[derive(Deserialize, Debug, Clone)]
 struct Package {
     meta: Option<MetaData>,
+    // Store paths are null unless packages.json was generated with them
+    #[serde(default)]
+    outputs: HashMap<String, Option<String>>,
     pname: String,
     version: String,
 }
@@ -16,26 +19,42 @@ struct Package {
 struct PkgJson {
     packages: HashMap<String, Package>,
 }
-pub async fn getmeta(channel: &str, rev: &str) -> Result<HashMap<String, Pkg>> {
+pub async fn getmeta(
+    channel: &str,
+    rev: &str,
+    fixtures: Option<&Fixtures>,
+) -> Result<HashMap<String, Pkg>> {
+    let output = if let Some(fixtures) = fixtures {
+        fixtures.packages_json(channel, rev)?
+    } else {
+        let url = format!("https://releases.nixos.org/{}/{}/packages.json.br", channel, rev);
 
-    let url = format!("https://releases.nixos.org/{}/{}/packages.json.br", channel, rev);
+        // reqwest brotli decompression
+        let client = reqwest::Client::builder()
+            .brotli(true)
+            .build()?;
+        client.get(&url).send().await?.error_for_status()?.bytes().await?.to_vec()
+    };
 
-    // reqwest brotli decompression
-    let client = reqwest::Client::builder()
-        .brotli(true)
-        .build()?;
-    let output = client.get(&url).send().await?.bytes().await?;
-
-    let output: PkgJson = serde_json::from_slice(&output).unwrap();
+    let output: PkgJson = serde_json::from_slice(&output)
+        .with_context(|| format!("Failed to parse packages.json of {}/{}", channel, rev))?;
 
     let data = output
         .packages
         .iter()
         .map(|(attr, pkg)| {
-            let metadata = pkg.meta.as_ref().unwrap().clone();
+            let metadata = pkg.meta.clone().unwrap_or_default();
             let store = Pkg {
                 attribute: attr.to_string(),
-                // store: outpath.split("/").last().unwrap().to_string(),
+                outputs: pkg
+                    .outputs
+                    .iter()
+                    .filter_map(|(name, outpath)| {
+                        let outpath = outpath.as_ref()?;
+                        let outpath = outpath.strip_prefix("/nix/store/").unwrap_or(outpath);
+                        Some((name.to_string(), outpath.to_string()))
+                    })
+                    .collect(),
                 meta: metadata,
                 pname: pkg.pname.clone(),
                 version: pkg.version.clone(),
@@ -44,11 +63,14 @@ pub async fn getmeta(channel: &str, rev: &str) -> Result<HashMap<String, Pkg>> {
         })
         .collect::<HashMap<String, Pkg>>();
 
-    return Ok(data);
+    Ok(data)
 }
 
-pub async fn getrevision(channel: &str, rev: &str) -> Result<String> {
+pub async fn getrevision(channel: &str, rev: &str, fixtures: Option<&Fixtures>) -> Result<String> {
+    if let Some(fixtures) = fixtures {
+        return Ok(fixtures.git_revision(channel, rev)?.trim().to_string());
+    }
     let url = format!("https://releases.nixos.org/{}/{}/git-revision", channel, rev);
-    let output = reqwest::get(&url).await?.text().await?;
-    Ok(output)
+    let output = reqwest::get(&url).await?.error_for_status()?.text().await?;
+    Ok(output.trim().to_string())
 }
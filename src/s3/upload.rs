@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use aws_sdk_s3::{primitives::ByteStream, Client};
+use log::info;
+use std::{
+    future::Future,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Somewhere generated databases can be published to
+pub trait UploadTarget {
+    /// Publish the file at `path` under `key`
+    fn put(
+        &self,
+        key: &str,
+        path: &Path,
+        content_encoding: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Publish `body` under `key`, replacing any previous object atomically
+    fn put_bytes(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Fetch the object under `key`, if it exists
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+}
+
+/// AWS S3 bucket, or any S3-compatible object store such as MinIO
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Target {
+    pub fn new(client: Client, bucket: &str) -> Self {
+        S3Target {
+            client,
+            bucket: bucket.to_string(),
+        }
+    }
+
+    /// S3-compatible endpoint at `endpoint_url`, optionally with path-style addressing
+    pub fn compatible(
+        config: &aws_config::SdkConfig,
+        endpoint_url: &str,
+        path_style: bool,
+        bucket: &str,
+    ) -> Self {
+        let config = aws_sdk_s3::config::Builder::from(config)
+            .endpoint_url(endpoint_url)
+            .force_path_style(path_style)
+            .build();
+        S3Target::new(Client::from_conf(config), bucket)
+    }
+}
+
+impl UploadTarget for S3Target {
+    async fn put(&self, key: &str, path: &Path, content_encoding: &str) -> Result<()> {
+        info!("Uploading {} to {}/{}", path.display(), self.bucket, key);
+        let body = ByteStream::from_path(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        self.client
+            .put_object()
+            .content_encoding(content_encoding)
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload {} to {}", path.display(), self.bucket))?;
+        Ok(())
+    }
+
+    async fn put_bytes(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<()> {
+        info!("Uploading {}/{}", self.bucket, key);
+        self.client
+            .put_object()
+            .content_type(content_type)
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload {} to {}", key, self.bucket))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let out = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+
+        match out {
+            Ok(out) => Ok(Some(out.body.collect().await?.into_bytes().to_vec())),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to fetch {} from {}", key, self.bucket))
+            }
+        }
+    }
+}
+
+/// Local directory, e.g. the root of a static file server
+///
+/// Files are stored as-is under `{dir}/{key}`, so the server has to send the content encoding.
+#[derive(Debug, Clone)]
+pub struct LocalTarget {
+    dir: PathBuf,
+}
+
+impl LocalTarget {
+    pub fn new(dir: &str) -> Self {
+        LocalTarget {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// `{dir}/{key}`, refusing keys that are absolute or would climb out of the directory
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if !Path::new(key)
+            .components()
+            .all(|x| matches!(x, Component::Normal(_)))
+        {
+            anyhow::bail!("Invalid key for {}: {}", self.dir.display(), key);
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+impl UploadTarget for LocalTarget {
+    async fn put(&self, key: &str, path: &Path, _content_encoding: &str) -> Result<()> {
+        let dest = self.path(key)?;
+        info!("Copying {} to {}", path.display(), dest.display());
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Copy next to the destination first, so readers never see a partial file
+        let tmp = tmp_path(&dest);
+        tokio::fs::copy(path, &tmp)
+            .await
+            .with_context(|| format!("Failed to copy {} to {}", path.display(), tmp.display()))?;
+        tokio::fs::rename(&tmp, &dest)
+            .await
+            .with_context(|| format!("Failed to replace {}", dest.display()))?;
+        Ok(())
+    }
+
+    async fn put_bytes(&self, key: &str, body: Vec<u8>, _content_type: &str) -> Result<()> {
+        let dest = self.path(key)?;
+        info!("Writing {}", dest.display());
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let tmp = tmp_path(&dest);
+        tokio::fs::write(&tmp, body)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &dest)
+            .await
+            .with_context(|| format!("Failed to replace {}", dest.display()))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+}
+
+/// Unique sibling of `dest`, as channels at the same revision may publish the same key at once
+fn tmp_path(dest: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(tmp)
+}
@@ -0,0 +1,230 @@
+// End-to-end runs of the s3 and ddb commands against the fixtures in tests/fixtures
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use libsnow_generators::s3::db::{SEARCH_QUERY, STORE_PATH_QUERY};
+use rusqlite::Connection;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Fresh copy of the fixtures, as runs move their markers
+fn workspace(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("libsnow-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    copy_dir(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"),
+        &dir.join("fixtures"),
+    );
+    dir
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+/// Run the generator offline from `dir`, where databases are built
+fn run(dir: &Path, args: &[&str]) {
+    let fixtures = dir.join("fixtures");
+    let output = Command::new(env!("CARGO_BIN_EXE_libsnow-generators"))
+        .current_dir(dir)
+        .arg("--offline")
+        .arg(&fixtures)
+        .arg("--processed")
+        .arg(fixtures.join("processed"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn marker(dir: &Path) -> String {
+    fs::read_to_string(dir.join("fixtures/processed/nixos/unstable/last")).unwrap()
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn s3_publishes_pending_releases() {
+    let dir = workspace("s3");
+    let out = dir.join("out");
+    run(
+        &dir,
+        &[
+            "s3",
+            "--upload",
+            "--target",
+            "local",
+            "--output-dir",
+            out.to_str().unwrap(),
+        ],
+    );
+
+    // The first release is behind the marker
+    let index = read_json(&out.join("index.json"));
+    let entries = index["nixos/unstable"].as_array().unwrap();
+    let releases = entries
+        .iter()
+        .map(|x| x["release"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(releases, ["nixos-24.05pre2.bbbb", "nixos-24.05pre3.cccc"]);
+    let latest = read_json(&out.join("nixos/unstable/latest"));
+    assert_eq!(latest, entries[1]);
+    assert_eq!(marker(&dir), "nixos-24.05pre3.cccc");
+
+    let mut db = vec![];
+    brotli::Decompressor::new(fs::File::open(out.join("cccc3333")).unwrap(), 4096)
+        .read_to_end(&mut db)
+        .unwrap();
+    assert_eq!(latest["size"], db.len());
+    assert_eq!(
+        latest["sha256"].as_str().unwrap(),
+        format!("{:x}", Sha256::digest(&db))
+    );
+
+    let path = dir.join("cccc3333.db");
+    fs::write(&path, db).unwrap();
+    let conn = Connection::open(&path).unwrap();
+    let version: String = conn
+        .query_row(
+            r#"SELECT "version" FROM "pkgs" WHERE "attribute" = 'hello'"#,
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(version, "2.13");
+    let (attribute, output): (String, String) = conn
+        .query_row(STORE_PATH_QUERY, ["cccc3333h-hello-2.13"], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap();
+    assert_eq!((attribute.as_str(), output.as_str()), ("hello", "out"));
+
+    let matches = conn
+        .prepare(SEARCH_QUERY)
+        .unwrap()
+        .query_map(rusqlite::params!["greeting", 10], |row| {
+            row.get::<_, String>(0)
+        })
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(matches.first().map(String::as_str), Some("hello"));
+
+    let licenses = conn
+        .prepare(
+            r#"SELECT coalesce("licenses"."spdx_id", "licenses"."short_name") FROM "pkg_licenses"
+            JOIN "licenses" ON "licenses"."id" = "pkg_licenses"."license"
+            WHERE "pkg_licenses"."attribute" = 'steam'
+            ORDER BY 1"#,
+        )
+        .unwrap()
+        .query_map([], |row| row.get::<_, String>(0))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(licenses, ["mit", "unfree"]);
+    let github: String = conn
+        .query_row(
+            r#"SELECT "maintainers"."github" FROM "pkg_maintainers"
+            JOIN "maintainers" ON "maintainers"."id" = "pkg_maintainers"."maintainer"
+            WHERE "pkg_maintainers"."attribute" = 'hello'"#,
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(github, "edolstra");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn s3_publishes_flake() {
+    let dir = workspace("flake");
+    let out = dir.join("out");
+    run(
+        &dir,
+        &[
+            "s3",
+            "--upload",
+            "--target",
+            "local",
+            "--output-dir",
+            out.to_str().unwrap(),
+            "--flake",
+            "github:snowfallorg/demo",
+        ],
+    );
+
+    let latest = read_json(&out.join("github_snowfallorg_demo/latest"));
+    assert_eq!(latest["rev"], "dddd4444");
+    assert!(out.join("dddd4444").exists());
+    // Flakes have no channel marker to move
+    assert_eq!(marker(&dir), "nixos-24.05pre1.aaaa");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ddb_indexes_store_paths() {
+    let dir = workspace("ddb");
+    let output = dir.join("paths.ndjson");
+    let args = [
+        "ddb",
+        "--sink",
+        "ndjson",
+        "--output",
+        output.to_str().unwrap(),
+        "--system",
+        "x86_64-linux,aarch64-linux",
+    ];
+    run(&dir, &args);
+
+    let lines = fs::read_to_string(&output).unwrap();
+    let entries = lines
+        .lines()
+        .map(|x| serde_json::from_str::<Value>(x).unwrap())
+        .collect::<Vec<_>>();
+    let paths = entries
+        .iter()
+        .map(|x| x["store"].as_str().unwrap())
+        .collect::<HashSet<_>>();
+    // Two releases, with paths of both systems
+    assert_eq!(paths.len(), entries.len());
+    assert!(paths.contains("bbbbhash-hello-2.12-man"));
+    assert!(paths.contains("ccccarm-hello-2.12"));
+    assert!(!paths.contains("aaaahash-hello-2.12"));
+    let steam = entries
+        .iter()
+        .find(|x| x["store"] == "cccchash-steam-1.0")
+        .unwrap();
+    assert_eq!(steam["attribute"], serde_json::json!(["steam"]));
+    assert_eq!(steam["system"], "x86_64-linux");
+    assert_eq!(marker(&dir), "nixos-24.05pre3.cccc");
+
+    // Nothing is pending any more
+    run(&dir, &args);
+    assert_eq!(fs::read_to_string(&output).unwrap(), lines);
+
+    fs::remove_dir_all(dir).unwrap();
+}